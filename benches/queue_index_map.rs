@@ -0,0 +1,62 @@
+//! Compares queueing a large map from raw linear tilesheet indices with
+//! `queue_index_map` against looking up each tile by key with `queue_tile`.
+//! Run with `cargo bench`.
+
+extern crate mosaic;
+
+use mosaic::{TileParams, TileSet};
+use std::time::{Duration, Instant};
+
+const SHEET: [i32; 2] = [16, 16];
+const MAP_WIDTH: usize = 256;
+const MAP_HEIGHT: usize = 256;
+const RUNS: u32 = 20;
+
+fn new_set() -> TileSet<u16> {
+    let mut set = TileSet::new_virtual(SHEET, [16, 16]);
+
+    for linear in 0..(SHEET[0] * SHEET[1]) as u16 {
+        let index = [i32::from(linear) % SHEET[0], i32::from(linear) / SHEET[0]];
+        set.register_tile(linear, index).unwrap();
+    }
+
+    set
+}
+
+/// The average time `queue` takes to queue the whole map into a cleared set.
+fn time<F: FnMut(&mut TileSet<u16>)>(set: &mut TileSet<u16>, mut queue: F) -> Duration {
+    let mut total = Duration::new(0, 0);
+
+    for _ in 0..RUNS {
+        set.clear_queue();
+        let start = Instant::now();
+        queue(set);
+        total += start.elapsed();
+    }
+
+    total / RUNS
+}
+
+fn main() {
+    let cells = (SHEET[0] * SHEET[1]) as usize;
+    let map: Vec<u16> = (0..MAP_WIDTH * MAP_HEIGHT)
+        .map(|i| (i * 7 % cells) as u16)
+        .collect();
+    let mut set = new_set();
+
+    let by_index = time(&mut set, |set| {
+        set.queue_index_map(&map, MAP_WIDTH, [0, 0], None::<TileParams>)
+            .unwrap();
+    });
+
+    let by_key = time(&mut set, |set| {
+        for (i, &key) in map.iter().enumerate() {
+            let cell = [(i % MAP_WIDTH) as i32, (i / MAP_WIDTH) as i32];
+            set.queue_tile(key, cell, None::<TileParams>).unwrap();
+        }
+    });
+
+    println!("{} tiles, average of {} runs", map.len(), RUNS);
+    println!("queue_index_map: {:?}", by_index);
+    println!("queue_tile:      {:?}", by_key);
+}
@@ -0,0 +1,132 @@
+//! Loading `TileSet`s from sprite sheets exported by
+//! [Aseprite](https://www.aseprite.org/).
+
+use ggez::graphics::Image;
+use serde_json;
+use std::{collections::BTreeMap, fs::File, io, path::Path};
+use {TileSet, TileSetError};
+
+#[derive(Deserialize)]
+struct Sheet {
+    frames: Frames,
+}
+
+/// Aseprite can export the frame table either as a map from frame names to
+/// frames or as an array of frames with a `filename` field.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Frames {
+    Hash(BTreeMap<String, Frame>),
+    Array(Vec<NamedFrame>),
+}
+
+#[derive(Deserialize)]
+struct NamedFrame {
+    filename: String,
+    frame: FrameRect,
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    frame: FrameRect,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct FrameRect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl TileSet<String> {
+    /// Create a new `TileSet` from an Aseprite JSON export at `json_path` and
+    /// its tilesheet image, registering each frame with its name as its key.
+    /// All frames must be the same size and aligned to a grid of that size,
+    /// which is used as the tile size.
+    pub fn from_aseprite_json<P: AsRef<Path>>(
+        json_path: P,
+        sheet: Image,
+    ) -> Result<TileSet<String>, AsepriteError> {
+        let file = File::open(json_path).map_err(AsepriteError::Io)?;
+        let sheet_data: Sheet = serde_json::from_reader(file).map_err(AsepriteError::Json)?;
+
+        let mut frames: Vec<(String, FrameRect)> = match sheet_data.frames {
+            Frames::Hash(frames) => frames
+                .into_iter()
+                .map(|(name, frame)| (name, frame.frame))
+                .collect(),
+            Frames::Array(frames) => frames
+                .into_iter()
+                .map(|frame| (frame.filename, frame.frame))
+                .collect(),
+        };
+
+        let tile_size = match frames.first() {
+            Some(&(_, rect)) => [rect.w, rect.h],
+            None => return Err(AsepriteError::NoFrames),
+        };
+
+        // Every other frame must match the first, so checking it is enough
+        // to keep the divisions below and the `TileSet` itself sound.
+        if tile_size[0] <= 0 || tile_size[1] <= 0 {
+            return Err(AsepriteError::EmptyFrame(frames.remove(0).0));
+        }
+
+        let mut set = TileSet::new(sheet, tile_size);
+
+        for (name, rect) in frames {
+            if rect.w != tile_size[0] || rect.h != tile_size[1] {
+                return Err(AsepriteError::FrameSizeMismatch(name));
+            }
+
+            if rect.x % rect.w != 0 || rect.y % rect.h != 0 {
+                return Err(AsepriteError::UnalignedFrame(name));
+            }
+
+            let index = [rect.x / rect.w, rect.y / rect.h];
+            set.register_tile(name, index).map_err(AsepriteError::Tile)?;
+        }
+
+        Ok(set)
+    }
+}
+
+/// Possible errors from loading an Aseprite sprite sheet.
+#[derive(Debug)]
+pub enum AsepriteError {
+    /// The JSON file couldn't be read.
+    Io(io::Error),
+    /// The JSON file isn't a valid Aseprite export.
+    Json(serde_json::Error),
+    /// The sprite sheet has no frames to take the tile size from.
+    NoFrames,
+    /// The named frame has no width or height.
+    EmptyFrame(String),
+    /// The named frame is a different size than the other frames.
+    FrameSizeMismatch(String),
+    /// The named frame isn't aligned to the tile grid.
+    UnalignedFrame(String),
+    /// A frame couldn't be registered to the `TileSet`.
+    Tile(TileSetError),
+}
+
+impl ::std::fmt::Display for AsepriteError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            AsepriteError::Io(err) => write!(f, "Couldn't read Aseprite JSON: {}", err),
+            AsepriteError::Json(err) => write!(f, "Invalid Aseprite JSON: {}", err),
+            AsepriteError::NoFrames => write!(f, "Aseprite sprite sheet has no frames"),
+            AsepriteError::EmptyFrame(name) => write!(f, "Frame `{}` is empty", name),
+            AsepriteError::FrameSizeMismatch(name) => {
+                write!(f, "Frame `{}` differs in size from the other frames", name)
+            }
+            AsepriteError::UnalignedFrame(name) => {
+                write!(f, "Frame `{}` isn't aligned to the tile grid", name)
+            }
+            AsepriteError::Tile(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl ::std::error::Error for AsepriteError {}
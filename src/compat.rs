@@ -0,0 +1,54 @@
+//! Construction of `ggez` types. Every `Point2` and `DrawParam` the crate
+//! builds goes through these functions, so that changes to the `ggez` API
+//! only need to be handled here.
+
+use ggez::graphics::{self, Color, DrawParam, Rect};
+
+/// Build a `ggez` point.
+pub(crate) fn point(x: f32, y: f32) -> graphics::Point2 {
+    graphics::Point2::new(x, y)
+}
+
+/// Build the `DrawParam` for drawing the `src` rectangle of an image, in UV
+/// coordinates, at `dest`.
+pub(crate) fn sprite(
+    src: Rect,
+    dest: graphics::Point2,
+    rotation: f32,
+    scale: graphics::Point2,
+    offset: graphics::Point2,
+    color: Option<Color>,
+) -> DrawParam {
+    DrawParam {
+        src,
+        dest,
+        rotation,
+        scale,
+        offset,
+        color,
+        ..Default::default()
+    }
+}
+
+/// Build the `DrawParam` for drawing a whole image, or everything in a
+/// batch, moved to `dest` and scaled by `scale`.
+pub(crate) fn transform(dest: graphics::Point2, scale: graphics::Point2) -> DrawParam {
+    DrawParam {
+        dest,
+        scale,
+        ..Default::default()
+    }
+}
+
+/// Replace the source rectangle and scale of `param`.
+pub(crate) fn with_src_scale(param: DrawParam, src: Rect, scale: graphics::Point2) -> DrawParam {
+    DrawParam { src, scale, ..param }
+}
+
+/// Replace the color of `param`.
+pub(crate) fn with_color(param: DrawParam, color: Color) -> DrawParam {
+    DrawParam {
+        color: Some(color),
+        ..param
+    }
+}
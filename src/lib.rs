@@ -4,116 +4,2799 @@
 //! [`ggez`](https://crates.io/crates/ggez).
 //!
 
+extern crate gfx;
 extern crate ggez;
 extern crate mint;
+extern crate rand;
+#[cfg(any(feature = "aseprite", feature = "mapping"))]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "aseprite")]
+extern crate serde_json;
 
-use ggez::graphics::{self, spritebatch::SpriteBatch, Color, DrawParam, Image, Rect};
+#[cfg(feature = "aseprite")]
+mod aseprite;
+mod compat;
+#[cfg(feature = "mapping")]
+mod mapping;
+
+#[cfg(feature = "aseprite")]
+pub use aseprite::AsepriteError;
+#[cfg(feature = "mapping")]
+pub use mapping::{MappedTile, TileMapping};
+
+use gfx::{pso::buffer::Structure, shade::ConstFormat, traits::Pod};
+use ggez::graphics::{
+    self, spritebatch::{SpriteBatch, SpriteIdx}, Canvas, Color, DrawParam, FilterMode, Image,
+    Rect, Shader, WrapMode,
+};
+use ggez::nalgebra;
 use mint::{Point2, Vector2};
-use std::{collections::HashMap, hash::Hash};
+use rand::Rng;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet}, hash::Hash, path::Path,
+};
+
+/// Register a table of tiles to a `TileSet`, written as
+/// `tiles!(set, { key => (x, y), ... })`, where `(x, y)` is the index of the
+/// tile in the tilesheet. Evaluates to a `Result<(), TileSetError>` holding
+/// the first error from `TileSet::register_tile`, after which no more tiles are
+/// registered.
+#[macro_export]
+macro_rules! tiles {
+    ($set:expr, { $($key:expr => ($x:expr, $y:expr)),* $(,)* }) => {{
+        let set = &mut $set;
+        let mut result: Result<(), $crate::TileSetError> = Ok(());
+        $(
+            if result.is_ok() {
+                result = set.register_tile($key, [$x, $y]);
+            }
+        )*
+        result
+    }};
+}
+
+/// A set of tiles made from a tilesheet image, optionally with metadata of
+/// type `M` attached to each tile.
+pub struct TileSet<Key: Hash + Eq, M = ()> {
+    tile_size: Vector2<i32>,
+    tile_cache: HashMap<Key, Tile<M>>,
+    sheet_dimensions: Vector2<i32>,
+    sheet: Option<Image>,
+    sheet_size: Vector2<i32>,
+    spritebatch: Option<SpriteBatch>,
+    fills_batch: bool,
+    queued: Vec<Placement>,
+    frozen: Option<SpriteBatch>,
+    max_tiles: Option<usize>,
+    batch_capacity: Option<usize>,
+    integer_scale: u32,
+    origin: CoordOrigin,
+    margins: Margins,
+    camera: Camera,
+    cell_inset: Vector2<i32>,
+    lod_threshold: f32,
+    lod_sheet: Option<Image>,
+    flush_threshold: Option<usize>,
+    skip_index: Option<Point2<i32>>,
+    variants: HashMap<Key, Vec<(Point2<i32>, f32)>>,
+}
+
+impl<Key: Hash + Eq, M> TileSet<Key, M> {
+    /// Create a new `TileSet` from an image and tile size.
+    pub fn new<S: Into<Vector2<i32>>>(sheet: Image, tile_size: S) -> Self {
+        let sheet_size = [sheet.width() as i32, sheet.height() as i32].into();
+
+        Self::with_sheet(Some(sheet), sheet_size, tile_size.into())
+    }
+
+    /// Create a new `TileSet` without a tilesheet image, for a tilesheet of
+    /// `sheet_dimensions` tiles of the given size. Tiles can be registered
+    /// and queued as usual, but drawing does nothing and there is no
+    /// `SpriteBatch`, so it can be used without a `ggez` context, such as in
+    /// tests.
+    pub fn new_virtual<D: Into<Vector2<i32>>, S: Into<Vector2<i32>>>(
+        sheet_dimensions: D,
+        tile_size: S,
+    ) -> Self {
+        let sheet_dimensions = sheet_dimensions.into();
+        let tile_size = tile_size.into();
+        let sheet_size = [
+            sheet_dimensions.x * tile_size.x,
+            sheet_dimensions.y * tile_size.y,
+        ].into();
+
+        Self::with_sheet(None, sheet_size, tile_size)
+    }
+
+    fn with_sheet(sheet: Option<Image>, sheet_size: Vector2<i32>, tile_size: Vector2<i32>) -> Self {
+        let margins = Margins::default();
+        let sheet_dimensions = grid_dimensions(sheet_size, tile_size, &margins);
+
+        Self {
+            tile_size,
+            tile_cache: HashMap::new(),
+            sheet_dimensions,
+            spritebatch: sheet.as_ref().map(|sheet| SpriteBatch::new(sheet.clone())),
+            fills_batch: true,
+            queued: Vec::new(),
+            frozen: None,
+            sheet,
+            sheet_size,
+            max_tiles: None,
+            batch_capacity: None,
+            integer_scale: 1,
+            origin: CoordOrigin::TopLeft,
+            margins,
+            camera: Camera::default(),
+            cell_inset: [0, 0].into(),
+            lod_threshold: 0.0,
+            lod_sheet: None,
+            flush_threshold: None,
+            skip_index: None,
+            variants: HashMap::new(),
+        }
+    }
+
+    /// Limit the number of distinct keys that can be registered to the
+    /// `TileSet`, counting keys with tiles and keys with variants. Registering
+    /// a new key past the limit fails with `TileSetError::CapacityExceeded`.
+    pub fn with_max_tiles(mut self, limit: usize) -> Self {
+        self.max_tiles = Some(limit);
+        self
+    }
+
+    /// Limit the number of tiles that can be queued at once. Queueing a tile
+    /// past the limit fails with `TileSetError::BatchFull`.
+    pub fn with_batch_capacity(mut self, limit: usize) -> Self {
+        self.batch_capacity = Some(limit);
+        self
+    }
+
+    /// Set how many tiles `queue_streaming` lets build up before drawing and
+    /// clearing them. Default is no limit.
+    pub fn with_flush_threshold(mut self, threshold: usize) -> Self {
+        self.flush_threshold = Some(threshold);
+        self
+    }
+
+    /// Set the margins, in pixels, between the edges of the tilesheet and the
+    /// tiles in it. Default is no margins.
+    pub fn with_margins(mut self, margins: Margins) -> Self {
+        self.sheet_dimensions = grid_dimensions(self.sheet_size, self.tile_size, &margins);
+        self.margins = margins;
+        self
+    }
+
+    /// Set where the origin of the tile grid is. Default is
+    /// `CoordOrigin::TopLeft`.
+    pub fn with_origin(mut self, origin: CoordOrigin) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    /// Load the tilesheet image at `path` from the `ggez` filesystem and
+    /// create a new `TileSet` from it with the given tile size.
+    pub fn load<P: AsRef<Path>, S: Into<Vector2<i32>>>(
+        ctx: &mut ggez::Context,
+        path: P,
+        tile_size: S,
+    ) -> ggez::GameResult<Self> {
+        let sheet = Image::new(ctx, path)?;
+
+        Ok(Self::new(sheet, tile_size))
+    }
+
+    /// Map any `(x, y)` to a tile index within the tilesheet bounds by
+    /// wrapping around each axis, including for negative values.
+    ///
+    /// Panics if the tilesheet is too small to hold a single tile.
+    pub fn wrapped_index(&self, x: i32, y: i32) -> Point2<i32> {
+        Point2 {
+            x: x.rem_euclid(self.sheet_dimensions.x),
+            y: y.rem_euclid(self.sheet_dimensions.y),
+        }
+    }
+
+    /// Check whether `index` is the position of a tile within the tilesheet
+    /// bounds, and so can be registered.
+    pub fn index_in_bounds<I: Into<Point2<i32>>>(&self, index: I) -> bool {
+        fits_within(index.into(), self.sheet_dimensions)
+    }
+
+    /// Get every tile index in the tilesheet that no registered key or variant
+    /// looks up, in row-major order.
+    pub fn unused_cells(&self) -> Vec<Point2<i32>> {
+        let used: HashSet<Point2<i32>> = self.registered_indices().collect();
+
+        self.sheet_cells()
+            .filter(|index| !used.contains(index))
+            .collect()
+    }
+
+    /// The index of every registered tile and variant, including repeats.
+    fn registered_indices(&self) -> impl Iterator<Item = Point2<i32>> + '_ {
+        self.registrations().map(|(_, index)| index)
+    }
+
+    /// The lookup value and index of every registered tile and variant.
+    fn registrations(&self) -> impl Iterator<Item = (&Key, Point2<i32>)> + '_ {
+        let variants = self.variants
+            .iter()
+            .flat_map(|(key, variants)| variants.iter().map(move |&(index, _)| (key, index)));
+
+        self.tile_cache
+            .iter()
+            .map(|(key, tile)| (key, tile.index))
+            .chain(variants)
+    }
+
+    /// Every tile index in the tilesheet, in row-major order.
+    fn sheet_cells(&self) -> impl Iterator<Item = Point2<i32>> {
+        let dims = self.sheet_dimensions;
+
+        (0..dims.y).flat_map(move |y| (0..dims.x).map(move |x| Point2 { x, y }))
+    }
+
+    /// Check every registered tile and variant against the current tilesheet
+    /// bounds, returning the lookup value and index of each one that is out
+    /// of range.
+    pub fn validate(&self) -> Result<(), Vec<(&Key, Point2<i32>)>> {
+        let invalid: Vec<_> = self.registrations()
+            .filter(|&(_, index)| !self.index_in_bounds(index))
+            .collect();
+
+        if invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid)
+        }
+    }
+
+    /// Change the size of the tiles in the tilesheet. Fails, leaving the
+    /// `TileSet` unchanged, with `TileSetError::InvalidSize` if either side of
+    /// `tile_size` isn't positive or too small for the cell inset, or with
+    /// `TileSetError::OutOfRange` if any registered tile or variant would be
+    /// outside of the resized tilesheet.
+    pub fn set_tile_size<S: Into<Vector2<i32>>>(
+        &mut self,
+        tile_size: S,
+    ) -> Result<(), TileSetError> {
+        let tile_size = tile_size.into();
+
+        if tile_size.x <= 0 || tile_size.y <= 0 || !inset_fits(self.cell_inset, tile_size) {
+            return Err(TileSetError::InvalidSize);
+        }
+        let sheet_dimensions = grid_dimensions(self.sheet_size, tile_size, &self.margins);
+
+        if self.registered_indices()
+            .any(|index| !fits_within(index, sheet_dimensions))
+        {
+            return Err(TileSetError::OutOfRange);
+        }
+
+        self.tile_size = tile_size;
+        self.sheet_dimensions = sheet_dimensions;
+
+        Ok(())
+    }
+
+    /// Register a tile from the tilesheet to the `TileSet` with the lookup
+    /// value of `key`.
+    pub fn register_tile<I: Into<Point2<i32>>>(
+        &mut self,
+        key: Key,
+        index: I,
+    ) -> Result<(), TileSetError> {
+        self.register_tile_rotated(key, index, 0.0)
+    }
+
+    /// Register a tile like `register_tile`, but always draw it rotated by
+    /// `base_rotation` radians about its center. This is useful for tiles
+    /// that are stored rotated in the tilesheet. Any rotation given when
+    /// queueing the tile is added on top of `base_rotation`.
+    ///
+    /// Tiles are rotated within their own bounds, so a tile that isn't square,
+    /// as reported by `is_square_tile`, only fits its cell when rotated by a
+    /// multiple of half a turn, and overlaps the neighboring cells otherwise.
+    pub fn register_tile_rotated<I: Into<Point2<i32>>>(
+        &mut self,
+        key: Key,
+        index: I,
+        base_rotation: f32,
+    ) -> Result<(), TileSetError> {
+        let index = index.into();
+
+        if !self.index_in_bounds(index) {
+            return Err(TileSetError::OutOfRange);
+        }
+
+        self.insert_tile(
+            key,
+            Tile {
+                index,
+                rotation: base_rotation,
+                meta: None,
+            },
+        )
+    }
+
+    /// Register a tile like `register_tile`, with `meta` attached to it.
+    pub fn register_tile_with_meta<I: Into<Point2<i32>>>(
+        &mut self,
+        key: Key,
+        index: I,
+        meta: M,
+    ) -> Result<(), TileSetError> {
+        let index = index.into();
+
+        if !self.index_in_bounds(index) {
+            return Err(TileSetError::OutOfRange);
+        }
+
+        self.insert_tile(
+            key,
+            Tile {
+                index,
+                rotation: 0.0,
+                meta: Some(meta),
+            },
+        )
+    }
+
+    /// Register the tiles in the rectangle of `size` tiles with its top-left
+    /// tile at `start`, taking a key for each tile from `keys` in row-major
+    /// order. Fails, registering nothing, with `TileSetError::CountMismatch` if
+    /// `keys` doesn't have exactly one key per tile in the rectangle, with
+    /// `TileSetError::OutOfRange` if the rectangle isn't inside the tilesheet,
+    /// or with `TileSetError::CapacityExceeded` if the new keys don't fit.
+    pub fn register_range_exact<I, S, K>(
+        &mut self,
+        start: I,
+        size: S,
+        keys: K,
+    ) -> Result<(), TileSetError>
+    where
+        I: Into<Point2<i32>>,
+        S: Into<Vector2<i32>>,
+        K: IntoIterator<Item = Key>,
+    {
+        let start = start.into();
+        let size = size.into();
+        let keys: Vec<Key> = keys.into_iter().collect();
+
+        let count = size.x.checked_mul(size.y);
+        if size.x < 0 || size.y < 0 || count.map(|count| count as usize) != Some(keys.len()) {
+            return Err(TileSetError::CountMismatch);
+        }
+
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let last = match (start.x.checked_add(size.x - 1), start.y.checked_add(size.y - 1)) {
+            (Some(x), Some(y)) => [x, y],
+            _ => return Err(TileSetError::OutOfRange),
+        };
+
+        if !self.index_in_bounds(start) || !self.index_in_bounds(last) {
+            return Err(TileSetError::OutOfRange);
+        }
+
+        let index_of = |i: usize| -> Point2<i32> {
+            let i = i as i32;
+            [start.x + i % size.x, start.y + i / size.x].into()
+        };
+
+        if let Some(limit) = self.max_tiles {
+            let new_keys: HashSet<&Key> = keys
+                .iter()
+                .enumerate()
+                .filter(|&(i, key)| {
+                    self.skip_index != Some(index_of(i)) && !self.is_registered(key)
+                })
+                .map(|(_, key)| key)
+                .collect();
+
+            if self.key_count() + new_keys.len() > limit {
+                return Err(TileSetError::CapacityExceeded);
+            }
+        }
+
+        for (i, key) in keys.into_iter().enumerate() {
+            let index = index_of(i);
+
+            if self.skip_index != Some(index) {
+                self.register_tile(key, index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Register every tile in the tilesheet that `name_fn` returns a key for
+    /// when called with the tile's index, skipping the tiles it returns `None`
+    /// for.
+    pub fn register_sheet_by_name<F: Fn(Point2<i32>) -> Option<Key>>(
+        &mut self,
+        name_fn: F,
+    ) -> Result<(), TileSetError> {
+        for index in self.sheet_cells() {
+            if self.skip_index == Some(index) {
+                continue;
+            }
+
+            if let Some(key) = name_fn(index) {
+                self.register_tile(key, index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the metadata attached to the tile with the lookup value `key`, if
+    /// it is registered and has metadata.
+    pub fn tile_meta(&self, key: &Key) -> Option<&M> {
+        self.tile_cache.get(key).and_then(|tile| tile.meta.as_ref())
+    }
+
+    /// Register `new` as an alias of the already registered tile `existing`,
+    /// so both keys look up the same tile from the tilesheet.
+    pub fn alias(&mut self, existing: &Key, new: Key) -> Result<(), TileSetError>
+    where
+        M: Clone,
+    {
+        let tile = self.tile_cache
+            .get(existing)
+            .cloned()
+            .ok_or(TileSetError::TileNotFound)?;
+
+        self.insert_tile(new, tile)
+    }
+
+    /// Register a list of interchangeable `(index, weight)` tiles for `key`,
+    /// to be picked between by `queue_variant` and `queue_variant_stable`.
+    /// A variant with twice the weight of another is picked twice as often.
+    /// Replaces any variants already registered for `key`, and is separate
+    /// from the tiles registered with `register_tile`. Fails with
+    /// `TileSetError::InvalidWeights` if `variants` is empty, any weight is
+    /// negative, or all of the weights are zero, and with
+    /// `TileSetError::CapacityExceeded` if `key` is new and the `TileSet` is
+    /// at its maximum number of keys.
+    pub fn register_variants(
+        &mut self,
+        key: Key,
+        variants: Vec<(Point2<i32>, f32)>,
+    ) -> Result<(), TileSetError> {
+        let total: f32 = variants.iter().map(|&(_, weight)| weight).sum();
+        let valid = total > 0.0 && variants.iter().all(|&(_, weight)| weight >= 0.0);
+
+        if !valid {
+            return Err(TileSetError::InvalidWeights);
+        }
+
+        if variants.iter().any(|&(index, _)| !self.index_in_bounds(index)) {
+            return Err(TileSetError::OutOfRange);
+        }
+
+        if !self.has_room_for(&key) {
+            return Err(TileSetError::CapacityExceeded);
+        }
+
+        self.variants.insert(key, variants);
+
+        Ok(())
+    }
+
+    /// Get the entry for the lookup value `key`, for registering it only if
+    /// it isn't registered already, or changing the tile it maps to.
+    pub fn entry(&mut self, key: Key) -> TileEntry<'_, Key, M> {
+        let full = !self.has_room_for(&key);
+
+        TileEntry {
+            entry: self.tile_cache.entry(key),
+            sheet_dimensions: self.sheet_dimensions,
+            full,
+        }
+    }
+
+    fn insert_tile(&mut self, key: Key, tile: Tile<M>) -> Result<(), TileSetError> {
+        if !self.has_room_for(&key) {
+            return Err(TileSetError::CapacityExceeded);
+        }
+
+        self.tile_cache.insert(key, tile);
+
+        Ok(())
+    }
+
+    /// Whether `key` can be registered without going over the maximum number
+    /// of tiles, either because it's registered already or there's room.
+    fn has_room_for(&self, key: &Key) -> bool {
+        let limit = match self.max_tiles {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        self.is_registered(key) || self.key_count() < limit
+    }
+
+    /// Whether `key` has a tile or variants registered.
+    fn is_registered(&self, key: &Key) -> bool {
+        self.tile_cache.contains_key(key) || self.variants.contains_key(key)
+    }
+
+    /// The number of distinct keys with a tile or variants registered.
+    fn key_count(&self) -> usize {
+        let variant_only = self.variants
+            .keys()
+            .filter(|key| !self.tile_cache.contains_key(key))
+            .count();
+
+        self.tile_cache.len() + variant_only
+    }
+
+    /// Queue a tile with the lookup value `key` to be drawn at `draw_location`,
+    /// with optional drawing options.
+    pub fn queue_tile<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        draw_location: P,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+
+        self.queue(&key, draw_location.into(), &options)
+    }
+
+    /// Queue one of the variants registered with `register_variants` for
+    /// `key` at `draw_location`, picked at random from `rng` by weight.
+    pub fn queue_variant<P: Into<Point2<i32>>, R: Rng, TP: Into<TileParams>>(
+        &mut self,
+        key: &Key,
+        draw_location: P,
+        rng: &mut R,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let roll = rng.gen::<f32>();
+
+        self.queue_variant_rolled(key, draw_location.into(), roll, options)
+    }
+
+    /// Queue one of the variants registered with `register_variants` for
+    /// `key` at `draw_location` like `queue_variant`, but pick it by weight
+    /// from a hash of `draw_location` instead of at random. The same cell
+    /// always gets the same variant, so the tiles don't change between
+    /// frames.
+    pub fn queue_variant_stable<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: &Key,
+        draw_location: P,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let cell = draw_location.into();
+
+        self.queue_variant_rolled(key, cell, cell_roll(cell), options)
+    }
+
+    /// Queue the variant for `key` that `roll`, in `[0, 1)`, lands on when
+    /// the variants are laid out end to end by weight.
+    fn queue_variant_rolled<TP: Into<TileParams>>(
+        &mut self,
+        key: &Key,
+        cell: Point2<i32>,
+        roll: f32,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let variants = self.variants.get(key).ok_or(TileSetError::TileNotFound)?;
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+
+        let total: f32 = variants.iter().map(|&(_, weight)| weight).sum();
+        let target = roll * total;
+        let mut reached = 0.0;
+        let mut index = variants[variants.len() - 1].0;
+        for &(variant, weight) in variants {
+            reached += weight;
+            if target < reached {
+                index = variant;
+                break;
+            }
+        }
+
+        let tile = Tile {
+            index,
+            rotation: 0.0,
+            meta: None,
+        };
+        let sprites = self.sprite_params(&tile, self.grid_to_pixel(cell), &options);
+
+        self.add_sprites(cell, sprites)
+    }
+
+    /// Queue a tile like `queue_tile`, returning the rectangle in pixels that
+    /// the tile was queued to fill, before any rotation. With a cell inset,
+    /// this is the part of the cell inside the inset.
+    pub fn queue_tile_bounded<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        draw_location: P,
+        options: Option<TP>,
+    ) -> Result<Rect, TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let cell = draw_location.into();
+        self.queue(&key, cell, &options)?;
+
+        let zoom = self.integer_scale as f32;
+        let scale = options.tile_scale() * zoom;
+        let pixel = self.inset_dest(snap_to_pixel(self.grid_to_pixel(cell), scale), scale);
+        let content = self.content_size();
+
+        Ok(Rect::new(pixel.x, pixel.y, content.x * scale.x, content.y * scale.y))
+    }
+
+    /// Queue a tile like `queue_tile`, but first draw the queued tiles with
+    /// `draw` and clear the queue if the flush threshold has been reached.
+    /// This bounds the size of the queue when drawing huge numbers of tiles
+    /// in a single frame, at the cost of more draw calls. Any tiles still
+    /// queued afterwards need to be drawn as usual.
+    pub fn queue_streaming<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        ctx: &mut ggez::Context,
+        key: Key,
+        draw_location: P,
+        options: Option<TP>,
+    ) -> ggez::GameResult<()> {
+        if let Some(threshold) = self.flush_threshold {
+            if self.queued.len() >= threshold {
+                self.draw(ctx)?;
+                self.clear_queue();
+            }
+        }
+
+        Ok(self.queue_tile(key, draw_location, options)?)
+    }
+
+    /// Queue a row of run-length encoded tiles, where each `(key, count)` run
+    /// is drawn `count` times left to right. The row is drawn at `row` tiles
+    /// below `origin`, starting from `origin`'s column. Runs of tiles at the
+    /// skip index are left out, but still take up their cells.
+    pub fn queue_rle_row<O: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        runs: &[(Key, u32)],
+        row: i32,
+        origin: O,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let origin = origin.into();
+        let mut x = origin.x;
+
+        for (key, count) in runs {
+            if self.is_skipped(key) {
+                x += *count as i32;
+                continue;
+            }
+
+            for _ in 0..*count {
+                self.queue(key, [x, origin.y + row].into(), &options)?;
+                x += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queue the tiles of a sparse map, where each entry of `map` is a tile's
+    /// position relative to `origin` and its lookup value. Tiles at the skip
+    /// index are left out.
+    pub fn queue_sparse<O: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        map: &HashMap<Point2<i32>, Key>,
+        origin: O,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let origin = origin.into();
+
+        for (position, key) in map {
+            if self.is_skipped(key) {
+                continue;
+            }
+
+            self.queue(
+                key,
+                [origin.x + position.x, origin.y + position.y].into(),
+                &options,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Queue the tiles of a map stored as linear tilesheet indices, counting
+    /// row by row from the top-left tile of the tilesheet, without looking up
+    /// any keys. The map is laid out in rows of `map_width` tiles with its
+    /// top-left tile at `origin`. Tiles at the skip index are left out. Fails
+    /// with `TileSetError::InvalidSize` if `map_width` is `0` and there are
+    /// indices to queue, or with `TileSetError::OutOfRange` at the first index
+    /// outside of the tilesheet, keeping the tiles queued before it.
+    pub fn queue_index_map<O: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        indices: &[u16],
+        map_width: usize,
+        origin: O,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let origin = origin.into();
+        let columns = self.sheet_dimensions.x.max(1);
+
+        if map_width == 0 && !indices.is_empty() {
+            return Err(TileSetError::InvalidSize);
+        }
+
+        for (i, &linear) in indices.iter().enumerate() {
+            let linear = i32::from(linear);
+            let index = [linear % columns, linear / columns].into();
+
+            if !self.index_in_bounds(index) {
+                return Err(TileSetError::OutOfRange);
+            }
+
+            if self.skip_index == Some(index) {
+                continue;
+            }
+
+            let cell = [
+                origin.x + (i % map_width) as i32,
+                origin.y + (i / map_width) as i32,
+            ].into();
+            let tile = Tile {
+                index,
+                rotation: 0.0,
+                meta: None,
+            };
+            let sprites = self.sprite_params(&tile, self.grid_to_pixel(cell), &options);
+            self.add_sprites(cell, sprites)?;
+        }
+
+        Ok(())
+    }
+
+    /// Queue the tiles of `map`, laid out in rows of `width` tiles with its
+    /// top-left tile at `origin`, tinting each tile with the color `tint_fn`
+    /// returns for its grid cell. The tint replaces the color of `options`.
+    /// Fails with `TileSetError::InvalidSize` if `width` is `0` and `map`
+    /// isn't empty.
+    pub fn queue_map_tinted<O, F, TP>(
+        &mut self,
+        map: &[Key],
+        width: usize,
+        origin: O,
+        tint_fn: F,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError>
+    where
+        O: Into<Point2<i32>>,
+        F: Fn(Point2<i32>) -> Color,
+        TP: Into<TileParams>,
+    {
+        let mut options = options.map(|tp| tp.into()).unwrap_or_default();
+        let origin = origin.into();
+
+        if width == 0 && !map.is_empty() {
+            return Err(TileSetError::InvalidSize);
+        }
+
+        for (i, key) in map.iter().enumerate() {
+            let cell = [
+                origin.x + (i % width) as i32,
+                origin.y + (i / width) as i32,
+            ].into();
+
+            if self.is_skipped(key) {
+                continue;
+            }
+
+            options.color = Some(tint_fn(cell));
+            self.queue(key, cell, &options)?;
+        }
+
+        Ok(())
+    }
+
+    /// Queue the tile with the lookup value `key` at every cell exactly
+    /// `radius` cells from `center` in Chebyshev distance, forming a square
+    /// ring. A `radius` of `0` queues only `center`, and a negative `radius`
+    /// queues nothing.
+    pub fn queue_ring<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        center: P,
+        radius: i32,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let center = center.into();
+
+        if radius < 0 {
+            return Ok(());
+        }
+
+        if radius == 0 {
+            return self.queue(&key, center, &options);
+        }
+
+        for x in -radius..=radius {
+            self.queue(&key, [center.x + x, center.y - radius].into(), &options)?;
+            self.queue(&key, [center.x + x, center.y + radius].into(), &options)?;
+        }
+
+        for y in 1 - radius..radius {
+            self.queue(&key, [center.x - radius, center.y + y].into(), &options)?;
+            self.queue(&key, [center.x + radius, center.y + y].into(), &options)?;
+        }
+
+        Ok(())
+    }
+
+    /// Queue a horizontal bar of `length_cells` tiles with the lookup value
+    /// `empty_key`, starting at `draw_location`, overlaid from the left with
+    /// tiles with the lookup value `full_key` up to `fraction` of its length.
+    /// `fraction` is clamped to `[0, 1]`. The last `full_key` tile is clipped
+    /// to the filled part of its cell, and the rotation and flips of
+    /// `options`, and the rotation of the registered tile, are ignored for it.
+    pub fn queue_bar<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        empty_key: Key,
+        full_key: Key,
+        draw_location: P,
+        length_cells: i32,
+        fraction: f32,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let start = draw_location.into();
+
+        for x in 0..length_cells {
+            self.queue(&empty_key, [start.x + x, start.y].into(), &options)?;
+        }
+
+        let filled = fraction.clamp(0.0, 1.0) * length_cells.max(0) as f32;
+        let whole = filled.floor() as i32;
+        for x in 0..whole {
+            self.queue(&full_key, [start.x + x, start.y].into(), &options)?;
+        }
+
+        let part = filled - whole as f32;
+        if part <= 0.0 {
+            return Ok(());
+        }
+
+        let cell = [start.x + whole, start.y].into();
+        let params = {
+            let tile = self.tile_cache.get(&full_key).ok_or(TileSetError::TileNotFound)?;
+            let tile = Tile {
+                index: tile.index,
+                rotation: 0.0,
+                meta: None,
+            };
+            let clip_options = TileParams {
+                rotation: None,
+                flip_x: false,
+                flip_y: false,
+                ..options
+            };
+
+            self.sprite_params(&tile, self.grid_to_pixel(cell), &clip_options)
+                .into_iter()
+                .map(|(param, emissive)| {
+                    let src = Rect::new(param.src.x, param.src.y, param.src.w * part, param.src.h);
+                    (compat::with_src_scale(param, src, param.scale), emissive)
+                })
+                .collect()
+        };
+
+        self.add_sprites(cell, params)
+    }
+
+    /// Queue a tile with the lookup value `key` stretched to fill `dest`, in
+    /// pixels, ignoring the tile grid. The `scale` and `int_scale` of
+    /// `options` are ignored, since the size of `dest` determines the scale.
+    pub fn queue_tile_rect<TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        dest: Rect,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let tile = self.tile_cache.get(&key).ok_or(TileSetError::TileNotFound)?;
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+
+        let zoom = self.integer_scale as f32;
+        let scale = compat::point(
+            dest.w / self.tile_size.x as f32 * zoom,
+            dest.h / self.tile_size.y as f32 * zoom,
+        );
+        let pixel = compat::point(dest.x * zoom, dest.y * zoom);
+        let sprites = self.with_shadow(pixel, &options, |pixel, options| {
+            vec![self.tile_param(tile, pixel, scale, options)]
+        });
+        let cell = self.pixel_to_grid(pixel);
+
+        self.add_sprites(cell, sprites)
+    }
+
+    /// Queue a tile with the lookup value `key` at a fractional position on
+    /// the tile grid, such as halfway between two cells.
+    pub fn queue_tile_grid_f<P: Into<Point2<f32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        grid: P,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let grid = grid.into();
+        let cell = [grid.x.floor() as i32, grid.y.floor() as i32].into();
+
+        self.queue_at(&key, cell, self.grid_f_to_pixel(grid), &options)
+    }
+
+    /// Queue a tile with the lookup value `key` at `draw_location` with its
+    /// texture scrolled by `uv_offset`, as a fraction of the tile's size. The
+    /// texture wraps around within the tile, so neighboring tiles in the
+    /// tilesheet are never sampled. The rotation and flips of `options`, and
+    /// the rotation of the registered tile, are ignored.
+    pub fn queue_tile_scrolled<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        draw_location: P,
+        uv_offset: Vector2<f32>,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let tile = self.tile_cache.get(&key).ok_or(TileSetError::TileNotFound)?;
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let cell = draw_location.into();
+
+        let zoom = self.integer_scale as f32;
+        let scale = options.tile_scale() * zoom;
+        let pixel = self.inset_dest(snap_to_pixel(self.grid_to_pixel(cell), scale), scale);
+        let uv = self.tile_uv_rect(tile.index);
+
+        // Each axis is split into the part of the tile shown before the wrap
+        // point and the part shown after it, as `(start, len)` fractions of
+        // the tile. Up to four pieces are needed to cover the cell.
+        let split = |offset: f32| {
+            let offset = offset.rem_euclid(1.0);
+            [(offset, 1.0 - offset), (0.0, offset)]
+        };
+        let (x_spans, y_spans) = (split(uv_offset.x), split(uv_offset.y));
+        let content = self.content_size();
+        let size = compat::point(content.x * scale.x, content.y * scale.y);
+
+        let sprites = self.with_shadow(pixel, &options, |pixel, options| {
+            let mut params = Vec::with_capacity(4);
+            let mut dest_y = pixel.y;
+            for &(src_y, len_y) in &y_spans {
+                let mut dest_x = pixel.x;
+                for &(src_x, len_x) in &x_spans {
+                    if len_x > 0.0 && len_y > 0.0 {
+                        let src = Rect::new(
+                            uv.x + src_x * uv.w,
+                            uv.y + src_y * uv.h,
+                            len_x * uv.w,
+                            len_y * uv.h,
+                        );
+                        params.push(compat::sprite(
+                            src,
+                            compat::point(dest_x, dest_y),
+                            0.0,
+                            scale,
+                            compat::point(0.0, 0.0),
+                            options.color,
+                        ));
+                    }
+                    dest_x += len_x * size.x;
+                }
+                dest_y += len_y * size.y;
+            }
+            params
+        });
+
+        self.add_sprites(cell, sprites)
+    }
+
+    fn queue(
+        &mut self,
+        key: &Key,
+        coords: Point2<i32>,
+        options: &TileParams,
+    ) -> Result<(), TileSetError> {
+        let pixel = self.grid_to_pixel(coords);
+
+        self.queue_at(key, coords, pixel, options)
+    }
+
+    /// Queue the tile with the lookup value `key` with its top-left corner at
+    /// `pixel`, recording it as queued at `cell`.
+    fn queue_at(
+        &mut self,
+        key: &Key,
+        cell: Point2<i32>,
+        pixel: graphics::Point2,
+        options: &TileParams,
+    ) -> Result<(), TileSetError> {
+        let tile = self.tile_cache.get(key).ok_or(TileSetError::TileNotFound)?;
+        let sprites = self.sprite_params(tile, pixel, options);
+
+        self.add_sprites(cell, sprites)
+    }
+
+    /// Build the `DrawParam`s for `tile` with its top-left corner at `pixel`,
+    /// with its shadow first if `options` has one, each paired with whether
+    /// it's emissive.
+    fn sprite_params(
+        &self,
+        tile: &Tile<M>,
+        pixel: graphics::Point2,
+        options: &TileParams,
+    ) -> Vec<(DrawParam, bool)> {
+        self.with_shadow(pixel, options, |pixel, options| {
+            vec![self.param_for(tile, pixel, options)]
+        })
+    }
+
+    /// Build the `DrawParam`s for a tile with its top-left corner at `pixel`
+    /// with `build`, preceded by the ones for its shadow if `options` has
+    /// one. The shadow is never emissive, even when the tile is, so that
+    /// `draw_layered` doesn't light it up.
+    fn with_shadow<F>(
+        &self,
+        pixel: graphics::Point2,
+        options: &TileParams,
+        build: F,
+    ) -> Vec<(DrawParam, bool)>
+    where
+        F: Fn(graphics::Point2, &TileParams) -> Vec<DrawParam>,
+    {
+        let mut sprites = Vec::with_capacity(2);
+
+        if let Some((offset, color)) = options.shadow {
+            let zoom = self.integer_scale as f32;
+            let shadow_pixel = compat::point(pixel.x + offset.x * zoom, pixel.y + offset.y * zoom);
+            let shadow_options = TileParams {
+                color: Some(color),
+                shadow: None,
+                emissive: false,
+                ..*options
+            };
+            sprites.extend(build(shadow_pixel, &shadow_options).into_iter().map(|p| (p, false)));
+        }
+
+        sprites.extend(build(pixel, options).into_iter().map(|p| (p, options.emissive)));
+        sprites
+    }
+
+    /// Add all of `sprites` to the batch, or none of them if they don't fit.
+    fn add_sprites(
+        &mut self,
+        cell: Point2<i32>,
+        sprites: Vec<(DrawParam, bool)>,
+    ) -> Result<(), TileSetError> {
+        if !self.batch_has_room(sprites.len()) {
+            return Err(TileSetError::BatchFull);
+        }
+
+        for (param, emissive) in sprites {
+            self.add_sprite(cell, param, emissive)?;
+        }
+
+        Ok(())
+    }
+
+    /// Build the `DrawParam` that `queue_tile` would queue for the tile with
+    /// the lookup value `key` at `draw_location`, without queueing it.
+    pub fn tile_draw_param<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &self,
+        key: &Key,
+        draw_location: P,
+        options: Option<TP>,
+    ) -> Result<DrawParam, TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+
+        self.param_at(key, self.grid_to_pixel(draw_location.into()), &options)
+    }
+
+    /// Build the `DrawParam` for the tile with the lookup value `key` with its
+    /// top-left corner at `pixel`.
+    fn param_at(
+        &self,
+        key: &Key,
+        pixel: graphics::Point2,
+        options: &TileParams,
+    ) -> Result<DrawParam, TileSetError> {
+        let tile = self.tile_cache.get(key).ok_or(TileSetError::TileNotFound)?;
+
+        Ok(self.param_for(tile, pixel, options))
+    }
+
+    fn param_for(
+        &self,
+        tile: &Tile<M>,
+        pixel: graphics::Point2,
+        options: &TileParams,
+    ) -> DrawParam {
+        let zoom = self.integer_scale as f32;
+        let scale = options.tile_scale() * zoom;
+
+        self.tile_param(tile, pixel, scale, options)
+    }
+
+    /// Set a tile index in the tilesheet that stands for an empty cell, such
+    /// as `(0, 0)` for maps where the first tile is blank. The bulk
+    /// registration helpers don't register it, and tiles registered to it are
+    /// skipped when queueing whole maps. Default is `None`.
+    pub fn set_skip_index(&mut self, index: Option<Point2<i32>>) {
+        self.skip_index = index;
+    }
+
+    /// Whether the tile with the lookup value `key` is registered to the skip
+    /// index.
+    fn is_skipped(&self, key: &Key) -> bool {
+        match (self.tile_cache.get(key), self.skip_index) {
+            (Some(tile), Some(skip)) => tile.index == skip,
+            _ => false,
+        }
+    }
+
+    /// A new, empty `SpriteBatch` for the tilesheet image, if there is one.
+    fn new_batch(&self) -> Option<SpriteBatch> {
+        self.sheet.as_ref().map(|sheet| SpriteBatch::new(sheet.clone()))
+    }
+
+    /// Whether `count` more sprites can be queued without going over the
+    /// batch capacity.
+    fn batch_has_room(&self, count: usize) -> bool {
+        match self.batch_capacity {
+            Some(limit) => self.queued.len() + count <= limit,
+            None => true,
+        }
+    }
+
+    fn add_sprite(
+        &mut self,
+        cell: Point2<i32>,
+        param: DrawParam,
+        emissive: bool,
+    ) -> Result<(), TileSetError> {
+        if !self.batch_has_room(1) {
+            return Err(TileSetError::BatchFull);
+        }
+
+        if self.fills_batch {
+            if let Some(ref mut batch) = self.spritebatch {
+                batch.add(param);
+            }
+        }
+
+        self.queued.push(Placement {
+            cell,
+            param,
+            emissive,
+        });
+
+        Ok(())
+    }
+
+    /// Build the `DrawParam` for drawing `tile` with its top-left corner at
+    /// `dest` and the final `scale` applied.
+    fn tile_param(
+        &self,
+        tile: &Tile<M>,
+        mut dest: graphics::Point2,
+        scale: graphics::Point2,
+        options: &TileParams,
+    ) -> DrawParam {
+        let rotation = tile.rotation + options.rotation.unwrap_or(0.0);
+        let mut offset = compat::point(0.0, 0.0);
+        dest = self.inset_dest(snap_to_pixel(dest, scale), scale);
+
+        if rotation != 0.0 || options.flip_x || options.flip_y {
+            // `ggez` applies the offset before scaling but translates back
+            // after, so the pivot ends up at `dest + offset` in pixels.
+            let content = self.content_size();
+            offset = compat::point(0.5, 0.5);
+            dest.x += content.x * scale.x / 2.0 - offset.x;
+            dest.y += content.y * scale.y / 2.0 - offset.y;
+        }
+
+        // `ggez` scales before rotating, so a negative scale flips the tile
+        // about its center before it is rotated.
+        let flip = |flipped: bool| if flipped { -1.0 } else { 1.0 };
+
+        compat::sprite(
+            self.tile_uv_rect(tile.index),
+            dest,
+            rotation,
+            compat::point(scale.x * flip(options.flip_x), scale.y * flip(options.flip_y)),
+            offset,
+            options.color,
+        )
+    }
+
+    /// Get the rectangle, in pixels, that the tile at `index` occupies in the
+    /// tilesheet image, accounting for the tilesheet margins.
+    pub fn debug_tile_pixel_rect<I: Into<Point2<i32>>>(&self, index: I) -> Rect {
+        let index = index.into();
+
+        Rect::new_i32(
+            self.margins.left + index.x * self.tile_size.x,
+            self.margins.top + index.y * self.tile_size.y,
+            self.tile_size.x,
+            self.tile_size.y,
+        )
+    }
+
+    /// Whether the tiles are as wide as they are tall. Tiles that aren't square
+    /// don't fit their cell when rotated by a quarter turn, and overlap the
+    /// neighboring cells on one axis while leaving gaps on the other.
+    pub fn is_square_tile(&self) -> bool {
+        self.tile_size.x == self.tile_size.y
+    }
+
+    /// Get the normalized width and height of a single tile in the tilesheet,
+    /// as used for the source rectangles of queued tiles. This excludes the
+    /// cell inset.
+    pub fn tile_uv_size(&self) -> Vector2<f32> {
+        let content = self.content_size();
+
+        Vector2 {
+            x: content.x / self.sheet_size.x as f32,
+            y: content.y / self.sheet_size.y as f32,
+        }
+    }
+
+    /// The normalized source rectangle of the tile at `index`, shrunk by the
+    /// cell inset.
+    fn tile_uv_rect(&self, index: Point2<i32>) -> Rect {
+        let sheet_w = self.sheet_size.x as f32;
+        let sheet_h = self.sheet_size.y as f32;
+        let pixels = self.debug_tile_pixel_rect(index);
+        let inset = self.cell_inset;
+        let content = self.content_size();
+
+        Rect::new(
+            (pixels.x + inset.x as f32) / sheet_w,
+            (pixels.y + inset.y as f32) / sheet_h,
+            content.x / sheet_w,
+            content.y / sheet_h,
+        )
+    }
+
+    /// Set an inset, in pixels, that every edge of every tile is shrunk by
+    /// when drawn, for tilesheets with padding around the art in each cell.
+    /// Tiles are drawn in the same place as without the inset, minus the
+    /// inset edges. Fails, leaving the inset unchanged, with
+    /// `TileSetError::InvalidSize` if the inset is negative or leaves nothing
+    /// of the tile on either axis. Default is no inset.
+    pub fn set_cell_inset<I: Into<Vector2<i32>>>(&mut self, inset: I) -> Result<(), TileSetError> {
+        let inset = inset.into();
+
+        if !inset_fits(inset, self.tile_size) {
+            return Err(TileSetError::InvalidSize);
+        }
+
+        self.cell_inset = inset;
+        Ok(())
+    }
+
+    /// The size, in unscaled pixels, of the part of a tile inside the cell
+    /// inset.
+    fn content_size(&self) -> graphics::Point2 {
+        compat::point(
+            (self.tile_size.x - 2 * self.cell_inset.x) as f32,
+            (self.tile_size.y - 2 * self.cell_inset.y) as f32,
+        )
+    }
+
+    /// Move `dest`, the top-left corner of a tile drawn at `scale`, to the
+    /// top-left corner of the part inside the cell inset.
+    fn inset_dest(&self, dest: graphics::Point2, scale: graphics::Point2) -> graphics::Point2 {
+        compat::point(
+            dest.x + self.cell_inset.x as f32 * scale.x,
+            dest.y + self.cell_inset.y as f32 * scale.y,
+        )
+    }
+
+    fn grid_to_pixel(&self, coords: Point2<i32>) -> graphics::Point2 {
+        self.grid_f_to_pixel([coords.x as f32, coords.y as f32].into())
+    }
+
+    fn grid_f_to_pixel(&self, coords: Point2<f32>) -> graphics::Point2 {
+        let zoom = self.integer_scale as f32;
+        let tile_w = self.tile_size.x as f32;
+        let tile_h = self.tile_size.y as f32;
+        let y = match self.origin {
+            CoordOrigin::TopLeft => coords.y * tile_h,
+            CoordOrigin::BottomLeft { height } => height as f32 - (coords.y + 1.0) * tile_h,
+        };
+
+        compat::point(coords.x * tile_w * zoom, y * zoom)
+    }
+
+    fn pixel_to_grid(&self, pixel: graphics::Point2) -> Point2<i32> {
+        let zoom = self.integer_scale as f32;
+        let tile_w = self.tile_size.x as f32;
+        let tile_h = self.tile_size.y as f32;
+        let x = pixel.x / zoom;
+        let y = pixel.y / zoom;
+
+        let row = match self.origin {
+            CoordOrigin::TopLeft => (y / tile_h).floor(),
+            CoordOrigin::BottomLeft { height } => ((height as f32 - y) / tile_h).ceil() - 1.0,
+        };
+
+        [(x / tile_w).floor() as i32, row as i32].into()
+    }
+
+    /// Draw each tile as a solid block of its average color in `draw` when the
+    /// camera zoom is below `zoom`. This has no effect until `build_lod` is
+    /// called. Default is `0.0` (always draw the full tiles).
+    pub fn set_lod_threshold(&mut self, zoom: f32) {
+        self.lod_threshold = zoom;
+    }
+
+    /// Build the low detail tilesheet used by `draw` when zoomed out past the
+    /// LOD threshold, made from the average color of each tile. This reads
+    /// the tilesheet back from the GPU, so it should be called once after the
+    /// `TileSet` is set up rather than every frame. Does nothing for a
+    /// `TileSet` without a tilesheet image.
+    pub fn build_lod(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        let data = match self.sheet {
+            Some(ref sheet) => sheet.to_rgba8(ctx)?,
+            None => return Ok(()),
+        };
+        let sheet_w = self.sheet_size.x as usize;
+        let sheet_h = self.sheet_size.y as usize;
+        let dims = self.sheet_dimensions;
+        let mut texels = Vec::with_capacity(dims.x as usize * dims.y as usize * 4);
+
+        for index in self.sheet_cells() {
+            let rect = self.debug_tile_pixel_rect(index);
+            let (left, top) = (rect.x as usize, rect.y as usize);
+            let (width, height) = (rect.w as usize, rect.h as usize);
+            let mut sum = [0u64; 4];
+
+            for y in top..top + height {
+                // `to_rgba8` returns the rows of the image from bottom to top.
+                let row = (sheet_h - 1 - y) * sheet_w;
+
+                for x in left..left + width {
+                    let pixel = &data[(row + x) * 4..(row + x) * 4 + 4];
+
+                    for (total, &channel) in sum.iter_mut().zip(pixel) {
+                        *total += u64::from(channel);
+                    }
+                }
+            }
+
+            let count = (width * height).max(1) as u64;
+            texels.extend(sum.iter().map(|&total| (total / count) as u8));
+        }
+
+        let mut lod_sheet = Image::from_rgba8(ctx, dims.x as u16, dims.y as u16, &texels)?;
+        lod_sheet.set_filter(FilterMode::Nearest);
+        self.lod_sheet = Some(lod_sheet);
+
+        Ok(())
+    }
+
+    /// Set the camera that the tiles are viewed through when drawn. Default
+    /// is a camera at `(0.0, 0.0)` with no zoom.
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    /// Get the camera that the tiles are viewed through when drawn.
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+
+    /// Find the grid cell under `mouse`, in screen pixels, when the tiles are
+    /// drawn through the camera set with `set_camera`.
+    pub fn pick_tile(&self, mouse: Point2<f32>) -> Point2<i32> {
+        self.pick_tile_with(mouse, self.camera.into())
+    }
+
+    /// Find the grid cell under `mouse`, in screen pixels, when the tiles are
+    /// drawn with `draw_with` and the same `camera`. A `camera` that can't be
+    /// inverted, such as one with a scale of `0.0`, is ignored.
+    pub fn pick_tile_with(&self, mouse: Point2<f32>, camera: DrawParam) -> Point2<i32> {
+        let inverse = camera
+            .into_matrix()
+            .try_inverse()
+            .unwrap_or_else(graphics::Matrix4::identity);
+        let world = inverse * nalgebra::Vector4::new(mouse.x, mouse.y, 0.0, 1.0);
+
+        self.pixel_to_grid(compat::point(world.x, world.y))
+    }
+
+    /// Iterate over every grid cell that overlaps `camera`, in world pixels,
+    /// row by row. Cells that only touch the edge of `camera` aren't included.
+    pub fn visible_cells(&self, camera: Rect) -> impl Iterator<Item = Point2<i32>> {
+        let zoom = self.integer_scale as f32;
+        let tile_w = self.tile_size.x as f32 * zoom;
+        let tile_h = self.tile_size.y as f32 * zoom;
+        let (top, bottom) = match self.origin {
+            CoordOrigin::TopLeft => (camera.y, camera.y + camera.h),
+            CoordOrigin::BottomLeft { height } => {
+                let height = height as f32 * zoom;
+                (height - (camera.y + camera.h), height - camera.y)
+            }
+        };
+
+        let first_x = (camera.x / tile_w).floor() as i32;
+        let last_x = ((camera.x + camera.w) / tile_w).ceil() as i32;
+        let first_y = (top / tile_h).floor() as i32;
+        let last_y = (bottom / tile_h).ceil() as i32;
+
+        (first_y..last_y)
+            .flat_map(move |y| (first_x..last_x).map(move |x| Point2 { x, y }))
+    }
+
+    /// Set an integer zoom factor applied to every queued tile. Both the
+    /// destination and the size of the tiles are multiplied by `factor`,
+    /// which keeps pixel art crisp. Default is `1` (no zoom).
+    ///
+    /// Panics if `factor` is `0`.
+    pub fn set_integer_scale(&mut self, factor: u32) {
+        assert!(factor > 0, "integer scale must be at least 1");
+        self.integer_scale = factor;
+    }
+
+    /// Set how the tilesheet is sampled outside of its bounds on the x and y
+    /// axes. Default is `WrapMode::Clamp` for both.
+    pub fn set_wrap_mode(&mut self, x: WrapMode, y: WrapMode) {
+        if let Some(ref mut sheet) = self.sheet {
+            sheet.set_wrap(x, y);
+
+            if let Some(ref mut batch) = self.spritebatch {
+                batch.set_image(sheet.clone());
+            }
+        }
+    }
+
+    /// Get a reference to the `SpriteBatch` that tiles are queued into.
+    ///
+    /// Panics if the `TileSet` has no tilesheet image.
+    pub fn sprite_batch(&self) -> &SpriteBatch {
+        self.spritebatch
+            .as_ref()
+            .expect("TileSet without a tilesheet image has no SpriteBatch")
+    }
+
+    /// Get a mutable reference to the `SpriteBatch` that tiles are queued
+    /// into, for functionality that `TileSet` doesn't provide. Sprites added
+    /// directly to the batch are only drawn by `draw` and its variants, not
+    /// by a `TileSetGroup`.
+    ///
+    /// Panics if the `TileSet` has no tilesheet image.
+    pub fn sprite_batch_mut(&mut self) -> &mut SpriteBatch {
+        self.spritebatch
+            .as_mut()
+            .expect("TileSet without a tilesheet image has no SpriteBatch")
+    }
+
+    /// Clear the tile queue.
+    pub fn clear_queue(&mut self) {
+        if let Some(ref mut batch) = self.spritebatch {
+            batch.clear();
+        }
+
+        self.queued.clear();
+    }
+
+    /// Iterate over the destination, in pixels, and normalized source
+    /// rectangle of every queued sprite, in the order they were queued.
+    pub fn queued_tiles(&self) -> impl Iterator<Item = (Point2<f32>, Rect)> + '_ {
+        self.queued.iter().map(|placement| {
+            let dest = placement.param.dest;
+
+            (Point2 { x: dest.x, y: dest.y }, placement.param.src)
+        })
+    }
+
+    /// Replace the color of every queued tile with `color`, such as for fading
+    /// the whole scene out, without queueing the tiles again.
+    pub fn apply_tint(&mut self, color: Color) {
+        for placement in &mut self.queued {
+            placement.param = compat::with_color(placement.param, color);
+        }
+
+        if self.fills_batch {
+            self.rebuild_batch();
+        }
+    }
+
+    /// Rebuild the `SpriteBatch` from the queued tiles.
+    fn rebuild_batch(&mut self) {
+        if let Some(ref mut batch) = self.spritebatch {
+            batch.clear();
+
+            for placement in &self.queued {
+                batch.add(placement.param);
+            }
+        }
+    }
+
+    /// Set whether queued tiles are added to the `SpriteBatch`, emptying it
+    /// when they aren't and rebuilding it from the queue when they are again.
+    fn set_fills_batch(&mut self, fills_batch: bool) {
+        self.fills_batch = fills_batch;
+
+        if fills_batch {
+            self.rebuild_batch();
+        } else if let Some(ref mut batch) = self.spritebatch {
+            batch.clear();
+        }
+    }
+
+    /// Remove every registered tile and variant and clear the tile queue and
+    /// any frozen snapshot, keeping the tilesheet and its configuration.
+    pub fn reset(&mut self) {
+        self.tile_cache.clear();
+        self.variants.clear();
+        self.clear_queue();
+        self.unfreeze();
+    }
+
+    /// Draw the tiles using `ctx`, viewed through the camera set with
+    /// `set_camera`.
+    pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        if self.camera.zoom < self.lod_threshold {
+            if let Some(ref lod_sheet) = self.lod_sheet {
+                return self.draw_lod(ctx, lod_sheet);
+            }
+        }
+
+        match self.spritebatch {
+            Some(ref batch) => graphics::draw_ex(ctx, batch, self.camera.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Draw every queued tile as a single texel of `lod_sheet`, keeping the
+    /// position and size of the tile.
+    fn draw_lod(&self, ctx: &mut ggez::Context, lod_sheet: &Image) -> ggez::GameResult<()> {
+        let sheet_w = self.sheet_size.x as f32;
+        let sheet_h = self.sheet_size.y as f32;
+        let dims = self.sheet_dimensions;
+        let mut batch = SpriteBatch::new(lod_sheet.clone());
+
+        for placement in &self.queued {
+            let param = placement.param;
+            let index_x = ((param.src.x * sheet_w - self.margins.left as f32)
+                / self.tile_size.x as f32)
+                .floor();
+            let index_y = ((param.src.y * sheet_h - self.margins.top as f32)
+                / self.tile_size.y as f32)
+                .floor();
+
+            // The LOD sheet is one texel per tile, so the scale is the size
+            // of the tile in pixels.
+            let src = Rect::new(
+                index_x / dims.x as f32,
+                index_y / dims.y as f32,
+                1.0 / dims.x as f32,
+                1.0 / dims.y as f32,
+            );
+            let scale = compat::point(
+                param.scale.x * param.src.w * sheet_w,
+                param.scale.y * param.src.h * sheet_h,
+            );
+            batch.add(compat::with_src_scale(param, src, scale));
+        }
+
+        graphics::draw_ex(ctx, &batch, self.camera.into())
+    }
+
+    /// Draw the tiles using `ctx`, transformed by `camera`.
+    pub fn draw_with(&self, ctx: &mut ggez::Context, camera: DrawParam) -> ggez::GameResult<()> {
+        match self.spritebatch {
+            Some(ref batch) => graphics::draw_ex(ctx, batch, camera),
+            None => Ok(()),
+        }
+    }
+
+    /// Draw the tiles using `ctx`, with emissive tiles drawn to `light_canvas`
+    /// and all other tiles drawn to `color_canvas`. Drawing is directed back
+    /// to the screen afterwards.
+    pub fn draw_layered(
+        &self,
+        ctx: &mut ggez::Context,
+        color_canvas: &Canvas,
+        light_canvas: &Canvas,
+    ) -> ggez::GameResult<()> {
+        let (mut color_batch, mut light_batch) = match self.sheet {
+            Some(ref sheet) => (SpriteBatch::new(sheet.clone()), SpriteBatch::new(sheet.clone())),
+            None => return Ok(()),
+        };
+
+        for placement in &self.queued {
+            if placement.emissive {
+                light_batch.add(placement.param);
+            } else {
+                color_batch.add(placement.param);
+            }
+        }
+
+        graphics::set_canvas(ctx, Some(color_canvas));
+        let result = graphics::draw_ex(ctx, &color_batch, self.camera.into()).and_then(|_| {
+            graphics::set_canvas(ctx, Some(light_canvas));
+            graphics::draw_ex(ctx, &light_batch, self.camera.into())
+        });
+        graphics::set_canvas(ctx, None);
+
+        result
+    }
+
+    /// Draw the tiles using `ctx` with the color of every tile multiplied by
+    /// `filter`, for effects over the whole `TileSet`. Tiles queued without a
+    /// color are treated as white.
+    pub fn draw_with_color_filter(
+        &self,
+        ctx: &mut ggez::Context,
+        filter: Color,
+    ) -> ggez::GameResult<()> {
+        // The outer `DrawParam` color of a `SpriteBatch` only applies to
+        // sprites without their own color, so it can't be used to tint.
+        let mut batch = match self.new_batch() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+
+        for placement in &self.queued {
+            let color = placement.param.color.unwrap_or(graphics::WHITE);
+
+            let filtered = Color::new(
+                color.r * filter.r,
+                color.g * filter.g,
+                color.b * filter.b,
+                color.a * filter.a,
+            );
+            batch.add(compat::with_color(placement.param, filtered));
+        }
+
+        graphics::draw_ex(ctx, &batch, self.camera.into())
+    }
+
+    /// Draw the tiles using `ctx` like `draw`, but sampled from `alt_sheet`
+    /// instead of the tilesheet, such as an all-white version of it for a
+    /// damage flash. `alt_sheet` must be the same size, in pixels, and have
+    /// the same layout as the tilesheet, since the queued tiles keep their
+    /// normalized source rectangles.
+    pub fn draw_with_sheet(
+        &self,
+        ctx: &mut ggez::Context,
+        alt_sheet: &Image,
+    ) -> ggez::GameResult<()> {
+        let mut batch = SpriteBatch::new(alt_sheet.clone());
+
+        for placement in &self.queued {
+            batch.add(placement.param);
+        }
+
+        graphics::draw_ex(ctx, &batch, self.camera.into())
+    }
+
+    /// Draw the tile with the lookup value `key` immediately using `ctx`, with
+    /// its top-left corner at `dest` in pixels and optional drawing options.
+    /// The queued tiles are left untouched.
+    pub fn draw_tile<TP: Into<TileParams>>(
+        &self,
+        ctx: &mut ggez::Context,
+        key: &Key,
+        dest: Point2<f32>,
+        options: Option<TP>,
+    ) -> ggez::GameResult<()> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+
+        let zoom = self.integer_scale as f32;
+        let pixel = compat::point(dest.x * zoom, dest.y * zoom);
+        let param = self.param_at(key, pixel, &options)?;
+
+        match self.sheet {
+            Some(ref sheet) => graphics::draw_ex(ctx, sheet, param),
+            None => Ok(()),
+        }
+    }
+
+    /// Draw the whole tilesheet image using `ctx` with its top-left corner at
+    /// `dest` in pixels, scaled by `scale`. This is useful for checking that
+    /// the tilesheet loaded correctly.
+    pub fn draw_sheet(
+        &self,
+        ctx: &mut ggez::Context,
+        dest: Point2<f32>,
+        scale: f32,
+    ) -> ggez::GameResult<()> {
+        let sheet = match self.sheet {
+            Some(ref sheet) => sheet,
+            None => return Ok(()),
+        };
+
+        graphics::draw_ex(
+            ctx,
+            sheet,
+            compat::transform(compat::point(dest.x, dest.y), compat::point(scale, scale)),
+        )
+    }
+
+    /// Draw the tiles using `ctx` with a separate draw call for each tile
+    /// instead of the spritebatch, in the order they were queued. This is
+    /// slower than `draw`, but useful for debugging or when the graphics
+    /// state needs to change between tiles.
+    pub fn draw_unbatched(&self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        let sheet = match self.sheet {
+            Some(ref sheet) => sheet,
+            None => return Ok(()),
+        };
+
+        // Each tile is drawn with its own `DrawParam`, so the camera goes on
+        // the transform stack instead.
+        let transform = DrawParam::from(self.camera).into_matrix() * graphics::get_transform(ctx);
+        graphics::push_transform(ctx, Some(transform));
+        graphics::apply_transformations(ctx)?;
+
+        let result = self.queued
+            .iter()
+            .try_for_each(|placement| graphics::draw_ex(ctx, sheet, placement.param));
+
+        graphics::pop_transform(ctx);
+        graphics::apply_transformations(ctx)?;
+
+        result
+    }
+
+    /// Draw the tiles like `draw`, then call `overlay_fn` with `ctx` for each
+    /// queued sprite, in the order they were queued, for drawing decorations
+    /// such as outlines on top of them. `overlay_fn` is given the destination
+    /// of the sprite, as from `queued_tiles`, and the rectangle it covers in
+    /// pixels before any rotation. It draws through the same camera as the
+    /// tiles, so both are in world pixels.
+    pub fn draw_with_overlay<F>(
+        &self,
+        ctx: &mut ggez::Context,
+        overlay_fn: F,
+    ) -> ggez::GameResult<()>
+    where
+        F: Fn(&mut ggez::Context, Point2<f32>, Rect) -> ggez::GameResult<()>,
+    {
+        self.draw(ctx)?;
+
+        let transform = DrawParam::from(self.camera).into_matrix() * graphics::get_transform(ctx);
+        graphics::push_transform(ctx, Some(transform));
+        graphics::apply_transformations(ctx)?;
+
+        let result = self.queued.iter().try_for_each(|placement| {
+            let param = placement.param;
+            let size = compat::point(
+                (param.src.w * self.sheet_size.x as f32 * param.scale.x).abs(),
+                (param.src.h * self.sheet_size.y as f32 * param.scale.y).abs(),
+            );
+
+            // Rotated and flipped tiles are drawn about their center, with
+            // `dest` moved there by `tile_param`.
+            let (mut x, mut y) = (param.dest.x, param.dest.y);
+            if param.offset.x != 0.0 || param.offset.y != 0.0 {
+                x += param.offset.x - size.x / 2.0;
+                y += param.offset.y - size.y / 2.0;
+            }
+
+            let dest = Point2 {
+                x: param.dest.x,
+                y: param.dest.y,
+            };
+            overlay_fn(ctx, dest, Rect::new(x, y, size.x, size.y))
+        });
+
+        graphics::pop_transform(ctx);
+        graphics::apply_transformations(ctx)?;
+
+        result
+    }
+
+    /// Draw the tiles using `ctx`, ordered by the value `key_fn` returns for
+    /// the grid cell of each tile, from lowest to highest. Tiles with equal
+    /// values are drawn in the order they were queued.
+    pub fn draw_sorted<F: Fn(Point2<i32>) -> i64>(
+        &self,
+        ctx: &mut ggez::Context,
+        key_fn: F,
+    ) -> ggez::GameResult<()> {
+        let mut placements: Vec<_> = self.queued.iter().collect();
+        placements.sort_by_key(|placement| key_fn(placement.cell));
+
+        let mut batch = match self.new_batch() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+
+        for placement in placements {
+            batch.add(placement.param);
+        }
+
+        graphics::draw_ex(ctx, &batch, self.camera.into())
+    }
+
+    /// Draw the tiles using `ctx` with `shader`, after sending `uniforms` to
+    /// it. The previously active shader is restored afterwards.
+    pub fn draw_with_shader<U: Structure<ConstFormat> + Pod + Copy + 'static>(
+        &self,
+        ctx: &mut ggez::Context,
+        shader: &Shader<U>,
+        uniforms: &U,
+    ) -> ggez::GameResult<()> {
+        shader.send(ctx, *uniforms)?;
+        let _lock = graphics::use_shader(ctx, shader);
+
+        match self.spritebatch {
+            Some(ref batch) => graphics::draw_ex(ctx, batch, self.camera.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Take a snapshot of the tiles currently queued, which can be drawn with
+    /// `draw_frozen` even after the queue is cleared.
+    pub fn freeze(&mut self) {
+        self.frozen = self.spritebatch.clone();
+    }
+
+    /// Discard the snapshot taken by `freeze`.
+    pub fn unfreeze(&mut self) {
+        self.frozen = None;
+    }
+
+    /// Draw the tiles snapshotted by `freeze` using `ctx`. Draws nothing if
+    /// there is no snapshot.
+    pub fn draw_frozen(&self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        match self.frozen {
+            Some(ref frozen) => graphics::draw_ex(ctx, frozen, self.camera.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Convert an integer `mint` point into a `ggez` point.
+pub fn to_ggez_point(point: Point2<i32>) -> graphics::Point2 {
+    compat::point(point.x as f32, point.y as f32)
+}
+
+/// Convert a `ggez` point into an integer `mint` point, truncating each
+/// coordinate towards zero.
+pub fn from_ggez_point(point: graphics::Point2) -> Point2<i32> {
+    [point.x as i32, point.y as i32].into()
+}
+
+/// Compute how many whole tiles of `tile_size` fit in a tilesheet of
+/// `sheet_size` pixels inside of `margins`.
+fn grid_dimensions(
+    sheet_size: Vector2<i32>,
+    tile_size: Vector2<i32>,
+    margins: &Margins,
+) -> Vector2<i32> {
+    [
+        (sheet_size.x - margins.left - margins.right) / tile_size.x,
+        (sheet_size.y - margins.top - margins.bottom) / tile_size.y,
+    ].into()
+}
+
+/// Whether `index` is a cell of a grid with the given `dimensions`.
+fn fits_within(index: Point2<i32>, dimensions: Vector2<i32>) -> bool {
+    index.x >= 0 && index.y >= 0 && index.x < dimensions.x && index.y < dimensions.y
+}
+
+/// Whether a cell inset of `inset` leaves part of a tile of `tile_size` to
+/// draw on both axes.
+fn inset_fits(inset: Vector2<i32>, tile_size: Vector2<i32>) -> bool {
+    inset.x >= 0 && inset.y >= 0 && 2 * inset.x < tile_size.x && 2 * inset.y < tile_size.y
+}
+
+/// Round `dest` to the nearest whole pixel if `scale` is a whole number on
+/// both axes, so tiles drawn at integer scales stay crisp.
+fn snap_to_pixel(dest: graphics::Point2, scale: graphics::Point2) -> graphics::Point2 {
+    if scale.x.fract() == 0.0 && scale.y.fract() == 0.0 {
+        compat::point(dest.x.round(), dest.y.round())
+    } else {
+        dest
+    }
+}
+
+/// Hash `cell` to a number in `[0, 1)`, for picking variants that stay the
+/// same from frame to frame.
+fn cell_roll(cell: Point2<i32>) -> f32 {
+    let mut hash = (u64::from(cell.x as u32) << 32) | u64::from(cell.y as u32);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^= hash >> 33;
+
+    (hash >> 40) as f32 / (1u32 << 24) as f32
+}
+
+/// Margins, in pixels, between each edge of a tilesheet and its tiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Margins {
+    /// Margin above the first row of tiles.
+    pub top: i32,
+    /// Margin left of the first column of tiles.
+    pub left: i32,
+    /// Margin right of the last column of tiles.
+    pub right: i32,
+    /// Margin below the last row of tiles.
+    pub bottom: i32,
+}
+
+/// A view of the tiles of a `TileSet`, applied when drawing and undone when
+/// picking tiles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    /// The position, in unzoomed pixels, drawn at the top-left of the screen.
+    pub position: Point2<f32>,
+    /// The factor everything is scaled by when drawn.
+    pub zoom: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position: Point2 { x: 0.0, y: 0.0 },
+            zoom: 1.0,
+        }
+    }
+}
+
+impl From<Camera> for DrawParam {
+    fn from(camera: Camera) -> DrawParam {
+        compat::transform(
+            compat::point(
+                -camera.position.x * camera.zoom,
+                -camera.position.y * camera.zoom,
+            ),
+            compat::point(camera.zoom, camera.zoom),
+        )
+    }
+}
+
+/// The origin of the grid that tiles are queued on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoordOrigin {
+    /// `(0, 0)` is the top-left cell and y increases downwards, matching
+    /// screen coordinates.
+    TopLeft,
+    /// `(0, 0)` is the bottom-left cell of a world `height` pixels tall and
+    /// y increases upwards.
+    BottomLeft {
+        /// Height of the world in pixels.
+        height: i32,
+    },
+}
+
+/// A tile registered to a `TileSet`.
+#[derive(Debug, Clone)]
+struct Tile<M> {
+    index: Point2<i32>,
+    rotation: f32,
+    meta: Option<M>,
+}
+
+/// A group of `TileSet`s made from the same tilesheet image, which are drawn
+/// together in a single batch. Tiles are drawn in the order the sets were
+/// added to the group, so later sets are drawn on top of earlier ones.
+pub struct TileSetGroup<Key: Hash + Eq, M = ()> {
+    sets: Vec<TileSet<Key, M>>,
+}
+
+impl<Key: Hash + Eq, M> TileSetGroup<Key, M> {
+    /// Create a new, empty `TileSetGroup`.
+    pub fn new() -> Self {
+        Self { sets: Vec::new() }
+    }
+
+    /// Add `set` to the group, drawn on top of the sets already in it.
+    /// Returns the index of the set in the group. Fails with
+    /// `TileSetError::SheetMismatch` if the tilesheet size, tile size or
+    /// margins of `set` differ from the first set in the group.
+    pub fn push(&mut self, set: TileSet<Key, M>) -> Result<usize, TileSetError> {
+        if let Some(first) = self.sets.first() {
+            let matches = set.sheet_size == first.sheet_size
+                && set.tile_size == first.tile_size
+                && set.margins == first.margins;
+
+            if !matches {
+                return Err(TileSetError::SheetMismatch);
+            }
+        }
+
+        self.sets.push(set);
+        Ok(self.sets.len() - 1)
+    }
+
+    /// Get the set at `index` in the group.
+    pub fn get(&self, index: usize) -> Option<&TileSet<Key, M>> {
+        self.sets.get(index)
+    }
+
+    /// Get the set at `index` in the group mutably, for queueing tiles.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut TileSet<Key, M>> {
+        self.sets.get_mut(index)
+    }
+
+    /// Clear the tile queues of every set in the group.
+    pub fn clear_queues(&mut self) {
+        for set in &mut self.sets {
+            set.clear_queue();
+        }
+    }
+
+    /// Draw the tiles of every set in the group in a single batch using
+    /// `ctx`. The tilesheet and camera of the first set are used for drawing.
+    pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        let first = match self.sets.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+
+        let mut batch = match first.new_batch() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+
+        for placement in self.sets.iter().flat_map(|set| &set.queued) {
+            batch.add(placement.param);
+        }
+
+        graphics::draw_ex(ctx, &batch, first.camera.into())
+    }
+}
+
+impl<Key: Hash + Eq, M> Default for TileSetGroup<Key, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A set of tiles spread across several tilesheet images with the same tile
+/// size, for tile collections too large to fit in a single texture. Each
+/// image is drawn in its own batch, in the order the images were given, so
+/// tiles from later images are drawn on top of tiles from earlier ones.
+pub struct TileSetArray<Key: Hash + Eq, M = ()> {
+    sets: Vec<TileSet<Key, M>>,
+}
+
+impl<Key: Hash + Eq, M> TileSetArray<Key, M> {
+    /// Create a new `TileSetArray` from tilesheet images and their tile size.
+    pub fn new<S: Into<Vector2<i32>>>(sheets: Vec<Image>, tile_size: S) -> Self {
+        let tile_size = tile_size.into();
+
+        Self {
+            sets: sheets
+                .into_iter()
+                .map(|sheet| TileSet::new(sheet, tile_size))
+                .collect(),
+        }
+    }
+
+    /// Register the tile at `index` in the tilesheet image at `sheet` with the
+    /// lookup value of `key`, replacing any registration of `key` in the other
+    /// images. Fails with `TileSetError::OutOfRange` if there is no image at
+    /// `sheet` or `index` is outside of it, or with
+    /// `TileSetError::CapacityExceeded` if the `TileSet` for `sheet` is full,
+    /// leaving any existing registration of `key` in place.
+    pub fn register_tile<I: Into<Point2<i32>>>(
+        &mut self,
+        key: Key,
+        sheet: usize,
+        index: I,
+    ) -> Result<(), TileSetError> {
+        let index = index.into();
+
+        match self.sets.get(sheet) {
+            Some(set) if set.index_in_bounds(index) => {
+                if !set.has_room_for(&key) {
+                    return Err(TileSetError::CapacityExceeded);
+                }
+            }
+            _ => return Err(TileSetError::OutOfRange),
+        }
+
+        for (i, set) in self.sets.iter_mut().enumerate() {
+            if i != sheet {
+                set.tile_cache.remove(&key);
+            }
+        }
+
+        self.sets[sheet].register_tile(key, index)
+    }
+
+    /// Queue a tile with the lookup value `key` to be drawn at `draw_location`
+    /// from whichever tilesheet image it is registered in, with optional
+    /// drawing options.
+    pub fn queue_tile<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        key: Key,
+        draw_location: P,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let set = self.sets
+            .iter_mut()
+            .find(|set| set.tile_cache.contains_key(&key))
+            .ok_or(TileSetError::TileNotFound)?;
+
+        set.queue_tile(key, draw_location, options)
+    }
+
+    /// Get the `TileSet` for the tilesheet image at `sheet`.
+    pub fn get(&self, sheet: usize) -> Option<&TileSet<Key, M>> {
+        self.sets.get(sheet)
+    }
+
+    /// Get the `TileSet` for the tilesheet image at `sheet` mutably.
+    pub fn get_mut(&mut self, sheet: usize) -> Option<&mut TileSet<Key, M>> {
+        self.sets.get_mut(sheet)
+    }
+
+    /// Clear the tile queue of every tilesheet image.
+    pub fn clear_queue(&mut self) {
+        for set in &mut self.sets {
+            set.clear_queue();
+        }
+    }
+
+    /// Draw the tiles using `ctx`, with one batch per tilesheet image.
+    pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        for set in &self.sets {
+            set.draw(ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A `TileSet` that remembers the tiles it drew last, so drawing a frame where
+/// few tiles changed only updates the sprites for those tiles.
+///
+/// While wrapped, the `TileSet` doesn't add queued tiles to its own
+/// `SpriteBatch`, so `TileSet::draw`, `TileSet::freeze` and the other methods
+/// that use that batch see it empty. It's rebuilt from the queue by
+/// `into_inner`.
+pub struct TrackedTileSet<Key: Hash + Eq, M = ()> {
+    set: TileSet<Key, M>,
+    batch: Option<SpriteBatch>,
+    drawn: Vec<(SpriteIdx, DrawParam)>,
+}
+
+impl<Key: Hash + Eq, M> TrackedTileSet<Key, M> {
+    /// Create a new `TrackedTileSet` wrapping `set`.
+    pub fn new(mut set: TileSet<Key, M>) -> Self {
+        set.set_fills_batch(false);
+
+        Self {
+            batch: set.new_batch(),
+            set,
+            drawn: Vec::new(),
+        }
+    }
+
+    /// Get a reference to the wrapped `TileSet`.
+    pub fn tile_set(&self) -> &TileSet<Key, M> {
+        &self.set
+    }
+
+    /// Get a mutable reference to the wrapped `TileSet`, for queueing the
+    /// tiles of the next frame.
+    pub fn tile_set_mut(&mut self) -> &mut TileSet<Key, M> {
+        &mut self.set
+    }
+
+    /// Unwrap the `TileSet`, adding its queued tiles back to its own
+    /// `SpriteBatch`.
+    pub fn into_inner(mut self) -> TileSet<Key, M> {
+        self.set.set_fills_batch(true);
+        self.set
+    }
+
+    /// Draw the tiles queued to the wrapped `TileSet` using `ctx`, updating
+    /// only the sprites that differ from the last draw. Tiles are compared by
+    /// the order they were queued in, so queue the tiles in the same order
+    /// every frame. If fewer tiles are queued than were last drawn, every
+    /// sprite is rebuilt.
+    pub fn draw_incremental(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
+        let batch = match self.batch {
+            Some(ref mut batch) => batch,
+            None => return Ok(()),
+        };
+
+        if self.set.queued.len() < self.drawn.len() {
+            batch.clear();
+            self.drawn.clear();
+        }
+
+        for (i, placement) in self.set.queued.iter().enumerate() {
+            match self.drawn.get_mut(i) {
+                Some(&mut (sprite, ref mut param)) => {
+                    if *param != placement.param {
+                        batch.set(sprite, placement.param)?;
+                        *param = placement.param;
+                    }
+                }
+                None => {
+                    let sprite = batch.add(placement.param);
+                    self.drawn.push((sprite, placement.param));
+                }
+            }
+        }
+
+        graphics::draw_ex(ctx, batch, self.set.camera.into())
+    }
+}
+
+/// A map of grid cells to the keys of the tiles placed in them, along with
+/// the `TileSet` the tiles are drawn from.
+pub struct TileMap<Key: Hash + Eq, M = ()> {
+    set: TileSet<Key, M>,
+    cells: HashMap<Point2<i32>, Key>,
+    stroke: Option<Vec<CellEdit<Key>>>,
+    undo_stack: Vec<Vec<CellEdit<Key>>>,
+    redo_stack: Vec<Vec<CellEdit<Key>>>,
+}
+
+impl<Key: Hash + Eq, M> TileMap<Key, M> {
+    /// Create a new, empty `TileMap` drawing its tiles from `set`.
+    pub fn new(set: TileSet<Key, M>) -> Self {
+        Self {
+            set,
+            cells: HashMap::new(),
+            stroke: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+        }
+    }
+
+    /// Get a reference to the `TileSet` the tiles are drawn from.
+    pub fn tile_set(&self) -> &TileSet<Key, M> {
+        &self.set
+    }
+
+    /// Get a mutable reference to the `TileSet` the tiles are drawn from.
+    pub fn tile_set_mut(&mut self) -> &mut TileSet<Key, M> {
+        &mut self.set
+    }
+
+    /// Unwrap the `TileSet` the tiles are drawn from, discarding the map.
+    pub fn into_inner(self) -> TileSet<Key, M> {
+        self.set
+    }
+
+    /// Get the key of the tile in `cell`.
+    pub fn get<P: Into<Point2<i32>>>(&self, cell: P) -> Option<&Key> {
+        self.cells.get(&cell.into())
+    }
+
+    /// Get the key of the tile in the grid cell under `px`, in screen pixels,
+    /// when the tiles are drawn through the `TileSet`'s camera, as with
+    /// `TileSet::pick_tile`.
+    pub fn key_at_pixel(&self, px: Point2<f32>) -> Option<&Key> {
+        self.get(self.set.pick_tile(px))
+    }
+
+    /// Queue every tile in the map to the `TileSet`, with optional drawing
+    /// options.
+    pub fn queue_all<TP: Into<TileParams>>(
+        &mut self,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+
+        for (&cell, key) in &self.cells {
+            if self.set.is_skipped(key) {
+                continue;
+            }
+
+            self.set.queue(key, cell, &options)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Key: Hash + Eq + Clone, M> TileMap<Key, M> {
+    /// Place the tile with the lookup value `key` in `cell`, returning the key
+    /// that was there before. Fails with `TileSetError::TileNotFound` if `key`
+    /// isn't registered to the `TileSet`.
+    pub fn set<P: Into<Point2<i32>>>(
+        &mut self,
+        cell: P,
+        key: Key,
+    ) -> Result<Option<Key>, TileSetError> {
+        if !self.set.tile_cache.contains_key(&key) {
+            return Err(TileSetError::TileNotFound);
+        }
+
+        let cell = cell.into();
+        let previous = self.cells.insert(cell, key);
+        self.record(cell, &previous);
+
+        Ok(previous)
+    }
+
+    /// Remove the tile in `cell`, returning its key.
+    pub fn remove<P: Into<Point2<i32>>>(&mut self, cell: P) -> Option<Key> {
+        let cell = cell.into();
+        let previous = self.cells.remove(&cell);
+        self.record(cell, &previous);
+
+        previous
+    }
+
+    /// Start recording the changes made with `set` and `remove` as a single
+    /// stroke that can be undone. A stroke already being recorded is ended
+    /// first.
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.stroke = Some(Vec::new());
+    }
+
+    /// Stop recording the current stroke, making it the next one `undo`
+    /// reverts. Strokes without any changes are discarded.
+    pub fn end_stroke(&mut self) {
+        if let Some(stroke) = self.stroke.take() {
+            if !stroke.is_empty() {
+                self.undo_stack.push(stroke);
+                self.redo_stack.clear();
+            }
+        }
+    }
+
+    /// Revert the most recent stroke, returning whether there was one to
+    /// revert. A stroke being recorded is ended first.
+    pub fn undo(&mut self) -> bool {
+        self.end_stroke();
+
+        match self.undo_stack.pop() {
+            Some(stroke) => {
+                let reverted = self.apply_stroke(stroke);
+                self.redo_stack.push(reverted);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reapply the most recently undone stroke, returning whether there was
+    /// one to reapply. Changing the map with `set` or `remove` after undoing
+    /// discards the strokes that could be reapplied.
+    pub fn redo(&mut self) -> bool {
+        self.end_stroke();
+
+        match self.redo_stack.pop() {
+            Some(stroke) => {
+                let reapplied = self.apply_stroke(stroke);
+                self.undo_stack.push(reapplied);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record a change to `cell` in the current stroke, if there is one. Any
+    /// change, recorded or not, makes the undone strokes impossible to redo.
+    fn record(&mut self, cell: Point2<i32>, previous: &Option<Key>) {
+        self.redo_stack.clear();
+
+        if let Some(ref mut stroke) = self.stroke {
+            stroke.push(CellEdit {
+                cell,
+                key: previous.clone(),
+            });
+        }
+    }
+
+    /// Put back the keys recorded in `stroke`, latest change first, returning
+    /// the stroke that reverses it.
+    fn apply_stroke(&mut self, stroke: Vec<CellEdit<Key>>) -> Vec<CellEdit<Key>> {
+        stroke
+            .into_iter()
+            .rev()
+            .map(|edit| {
+                let replaced = match edit.key {
+                    Some(key) => self.cells.insert(edit.cell, key),
+                    None => self.cells.remove(&edit.cell),
+                };
+
+                CellEdit {
+                    cell: edit.cell,
+                    key: replaced,
+                }
+            })
+            .collect()
+    }
+}
+
+/// A change to a cell of a `TileMap`, with the key the cell held before it.
+struct CellEdit<Key> {
+    cell: Point2<i32>,
+    key: Option<Key>,
+}
+
+impl<M> TileSet<char, M> {
+    /// Measure the size in pixels of `text` as `queue_text_wrapped` lays it
+    /// out when no line needs wrapping: one monospace tile per character,
+    /// with each line of `text` one row of tiles below the previous one. Like
+    /// the queued text, runs of whitespace count as a single space and
+    /// whitespace at the start and end of a line isn't counted.
+    pub fn text_size(&self, text: &str) -> Vector2<i32> {
+        let zoom = self.integer_scale as i32;
+        let lines = wrap_text(text, usize::MAX);
+        let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        let rows = lines.len();
+
+        [
+            columns as i32 * self.tile_size.x * zoom,
+            rows as i32 * self.tile_size.y * zoom,
+        ].into()
+    }
+
+    /// Queue `text` with one tile per character, starting at `draw_location`
+    /// and wrapping lines at word boundaries so that no line is wider than
+    /// `max_width` pixels. Words longer than a line are split across lines.
+    /// Whitespace is not queued.
+    pub fn queue_text_wrapped<P: Into<Point2<i32>>, TP: Into<TileParams>>(
+        &mut self,
+        text: &str,
+        draw_location: P,
+        max_width: i32,
+        options: Option<TP>,
+    ) -> Result<(), TileSetError> {
+        let options = options.map(|tp| tp.into()).unwrap_or_default();
+        let location = draw_location.into();
+        let columns = (max_width / (self.tile_size.x * self.integer_scale as i32)).max(1);
+
+        for (row, line) in wrap_text(text, columns as usize).iter().enumerate() {
+            for (column, c) in line.iter().enumerate() {
+                if c.is_whitespace() {
+                    continue;
+                }
+
+                let cell = [location.x + column as i32, location.y + row as i32].into();
+                self.queue(c, cell, &options)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Break `text` into lines of at most `columns` characters, wrapping at word
+/// boundaries where possible.
+fn wrap_text(text: &str, columns: usize) -> Vec<Vec<char>> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line: Vec<char> = Vec::new();
+
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+
+            while word.len() > columns {
+                if !line.is_empty() {
+                    lines.push(line);
+                    line = Vec::new();
+                }
+
+                let rest = word.split_off(columns);
+                lines.push(word);
+                word = rest;
+            }
+
+            if line.is_empty() {
+                line = word;
+            } else if line.len() + 1 + word.len() <= columns {
+                line.push(' ');
+                line.extend(word);
+            } else {
+                lines.push(line);
+                line = word;
+            }
+        }
+
+        lines.push(line);
+    }
 
-/// A set of tiles made from a tilesheet image.
-pub struct TileSet<Key: Hash + Eq> {
-    tile_size: Vector2<i32>,
-    tile_cache: HashMap<Key, Point2<i32>>,
+    lines
+}
+
+#[cfg(feature = "debug-keys")]
+impl<Key: Hash + Eq + std::fmt::Display, M> TileSet<Key, M> {
+    /// Draw the key of each tile of `map` in its cell using `ctx`, for
+    /// finding out which tile is drawn where. `map` is laid out in rows of
+    /// `width` tiles, with its top-left tile at `origin`. The keys are drawn
+    /// through the same camera as the tiles. Fails with
+    /// `TileSetError::InvalidSize` if `width` is `0` and `map` isn't empty.
+    pub fn draw_debug_keys<O: Into<Point2<i32>>>(
+        &self,
+        ctx: &mut ggez::Context,
+        map: &[Key],
+        width: usize,
+        origin: O,
+    ) -> ggez::GameResult<()> {
+        if width == 0 && !map.is_empty() {
+            return Err(TileSetError::InvalidSize.into());
+        }
+
+        let font = graphics::Font::default_font()?;
+        let origin = origin.into();
+
+        let transform = DrawParam::from(self.camera).into_matrix() * graphics::get_transform(ctx);
+        graphics::push_transform(ctx, Some(transform));
+        graphics::apply_transformations(ctx)?;
+
+        let result = map.iter().enumerate().try_for_each(|(i, key)| {
+            let cell = [
+                origin.x + (i % width) as i32,
+                origin.y + (i / width) as i32,
+            ].into();
+            let text = graphics::Text::new(ctx, &key.to_string(), &font)?;
+
+            graphics::draw(ctx, &text, self.grid_to_pixel(cell), 0.0)
+        });
+
+        graphics::pop_transform(ctx);
+        graphics::apply_transformations(ctx)?;
+
+        result
+    }
+}
+
+/// An entry in a `TileSet` for a single lookup value, created by
+/// `TileSet::entry`.
+pub struct TileEntry<'a, Key: 'a, M: 'a> {
+    entry: Entry<'a, Key, Tile<M>>,
     sheet_dimensions: Vector2<i32>,
-    spritebatch: SpriteBatch,
+    full: bool,
 }
 
-impl<Key: Hash + Eq> TileSet<Key> {
-    /// Create a new `TileSet` from an image and tile size.
-    pub fn new<S: Into<Vector2<i32>>>(sheet: Image, tile_size: S) -> Self {
-        let tile_size = tile_size.into();
-        let sheet_dimensions = [
-            sheet.width() as i32 / tile_size.x,
-            sheet.height() as i32 / tile_size.y,
-        ].into();
+impl<'a, Key: 'a, M: 'a> TileEntry<'a, Key, M> {
+    /// The lookup value of this entry.
+    pub fn key(&self) -> &Key {
+        self.entry.key()
+    }
 
-        Self {
-            tile_size,
-            tile_cache: HashMap::new(),
-            sheet_dimensions,
-            spritebatch: SpriteBatch::new(sheet),
+    /// Register the tile at `index` if the lookup value isn't registered yet,
+    /// returning the index of the tile the lookup value maps to.
+    pub fn or_register<I: Into<Point2<i32>>>(
+        self,
+        index: I,
+    ) -> Result<Point2<i32>, TileSetError> {
+        self.or_register_with(|| index)
+    }
+
+    /// Register the tile at the index returned by `default` if the lookup
+    /// value isn't registered yet, returning the index of the tile the lookup
+    /// value maps to.
+    pub fn or_register_with<I: Into<Point2<i32>>, F: FnOnce() -> I>(
+        self,
+        default: F,
+    ) -> Result<Point2<i32>, TileSetError> {
+        match self.entry {
+            Entry::Occupied(entry) => Ok(entry.get().index),
+            Entry::Vacant(entry) => {
+                let index = default().into();
+
+                if !fits_within(index, self.sheet_dimensions) {
+                    return Err(TileSetError::OutOfRange);
+                }
+
+                if self.full {
+                    return Err(TileSetError::CapacityExceeded);
+                }
+
+                entry.insert(Tile {
+                    index,
+                    rotation: 0.0,
+                    meta: None,
+                });
+
+                Ok(index)
+            }
         }
     }
 
-    /// Register a tile from the tilesheet to the `TileSet` with the lookup
-    /// value of `key`.
-    pub fn register_tile<I: Into<Point2<i32>>>(
-        &mut self,
-        key: Key,
+    /// Change the index of the tile if the lookup value is registered, by
+    /// calling `f` with it. Fails with `TileSetError::OutOfRange`, leaving the
+    /// tile as it was, if the changed index is outside of the tilesheet.
+    pub fn and_modify<F: FnOnce(&mut Point2<i32>)>(mut self, f: F) -> Result<Self, TileSetError> {
+        if let Entry::Occupied(ref mut entry) = self.entry {
+            let mut index = entry.get().index;
+            f(&mut index);
+
+            if !fits_within(index, self.sheet_dimensions) {
+                return Err(TileSetError::OutOfRange);
+            }
+
+            entry.get_mut().index = index;
+        }
+
+        Ok(self)
+    }
+
+    /// Register the tile at `index`, replacing the tile the lookup value
+    /// maps to along with its rotation and metadata. Returns the index of the
+    /// replaced tile, if there was one.
+    pub fn insert<I: Into<Point2<i32>>>(
+        self,
         index: I,
-    ) -> Result<(), TileSetError> {
+    ) -> Result<Option<Point2<i32>>, TileSetError> {
         let index = index.into();
 
-        if index.x > self.sheet_dimensions.x || index.y > self.sheet_dimensions.y {
+        if !fits_within(index, self.sheet_dimensions) {
             return Err(TileSetError::OutOfRange);
         }
 
-        self.tile_cache.insert(key, index);
+        let tile = Tile {
+            index,
+            rotation: 0.0,
+            meta: None,
+        };
 
-        Ok(())
+        match self.entry {
+            Entry::Occupied(mut entry) => Ok(Some(entry.insert(tile).index)),
+            Entry::Vacant(entry) => {
+                if self.full {
+                    return Err(TileSetError::CapacityExceeded);
+                }
+
+                entry.insert(tile);
+                Ok(None)
+            }
+        }
     }
+}
 
-    /// Queue a tile with the lookup value `key` to be drawn at `draw_location`,
-    /// with optional drawing options.
-    pub fn queue_tile<P: Into<Point2<i32>>, TP: Into<TileParams>>(
-        &mut self,
-        key: Key,
-        draw_location: P,
-        options: Option<TP>,
-    ) -> Result<(), TileSetError> {
-        let tile = self.tile_cache.get(&key).ok_or(TileSetError::TileNotFound)?;
+/// A tile queued to be drawn, along with the grid cell it was queued at.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    cell: Point2<i32>,
+    param: DrawParam,
+    emissive: bool,
+}
 
-        let options = options.map(|tp| tp.into()).unwrap_or(TileParams {
-            color: None,
-            scale: None,
-        });
+/// Additional parameters for drawing tiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TileParams {
+    /// The optional color to draw the tile with.
+    pub color: Option<Color>,
+    /// Scale factor for drawing. Ignored if `int_scale` is set. Default is
+    /// `1.0` (no scaling).
+    pub scale: Option<graphics::Point2>,
+    /// Integer scale factor for drawing just this tile pixel-perfectly, such
+    /// as for crisp icons drawn by a `TileSet` with smoothly scaled tiles.
+    /// Takes precedence over `scale` when both are set. `Some(0)` is treated
+    /// as `None`. Default is `None` (use `scale`).
+    pub int_scale: Option<u32>,
+    /// Rotation in radians about the center of the tile, added to the
+    /// tile's registered rotation. Tiles that aren't square overlap the
+    /// neighboring cells when rotated by a quarter turn. Default is `0.0`
+    /// (no rotation).
+    pub rotation: Option<f32>,
+    /// Whether the tile is drawn to the light canvas rather than the color
+    /// canvas by `TileSet::draw_layered`. Default is `false`.
+    pub emissive: bool,
+    /// Whether the tile is mirrored horizontally about its center. Flips are
+    /// always applied before `rotation`, so a flip combined with a rotation by
+    /// a multiple of 90 degrees covers all 8 orientations of a square tile.
+    /// Default is `false`.
+    pub flip_x: bool,
+    /// Whether the tile is mirrored vertically about its center, before
+    /// `rotation` is applied. Default is `false`.
+    pub flip_y: bool,
+    /// An offset in pixels and a color to queue a shadow of the tile with,
+    /// drawn underneath the tile. The shadow is never emissive. Default is
+    /// no shadow.
+    pub shadow: Option<(Vector2<f32>, Color)>,
+}
 
-        let coords = draw_location.into();
-        let normal_x = 1.0 / self.sheet_dimensions.x as f32;
-        let normal_y = 1.0 / self.sheet_dimensions.y as f32;
+impl TileParams {
+    /// Create a new `TileParams` with every option at its default.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        self.spritebatch.add(DrawParam {
-            src: Rect::new(
-                normal_x * tile.x as f32,
-                normal_y * tile.y as f32,
-                normal_x,
-                normal_y,
-            ),
-            dest: graphics::Point2::new(
-                (coords.x * self.tile_size.x) as f32,
-                (coords.y * self.tile_size.y) as f32,
-            ),
-            color: options.color,
-            scale: options.scale.unwrap_or(graphics::Point2::new(1.0, 1.0)),
-            ..Default::default()
-        });
+    /// Set the color to draw the tile with.
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
 
-        Ok(())
+    /// Set the scale factor to draw the tile with on both axes.
+    pub fn scale(self, scale: f32) -> Self {
+        self.scale_xy(scale, scale)
     }
 
-    /// Clear the tile queue.
-    pub fn clear_queue(&mut self) {
-        self.spritebatch.clear();
+    /// Set separate horizontal and vertical scale factors to draw the tile
+    /// with.
+    pub fn scale_xy(mut self, x: f32, y: f32) -> Self {
+        self.scale = Some(compat::point(x, y));
+        self
     }
 
-    /// Draw the tiles using `ctx`.
-    pub fn draw(&self, ctx: &mut ggez::Context) -> ggez::GameResult<()> {
-        graphics::draw_ex(ctx, &self.spritebatch, Default::default())
+    /// Set an integer scale factor to draw the tile with on both axes, which
+    /// takes precedence over `scale`. A `factor` of `0` is ignored, using
+    /// `scale` instead.
+    pub fn int_scale(mut self, factor: u32) -> Self {
+        self.int_scale = Some(factor);
+        self
+    }
+
+    /// Set the rotation in radians to draw the tile with.
+    pub fn rotation(mut self, rotation: f32) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Mark the tile as emissive.
+    pub fn emissive(mut self) -> Self {
+        self.emissive = true;
+        self
+    }
+
+    /// Mirror the tile horizontally.
+    pub fn flip_x(mut self) -> Self {
+        self.flip_x = true;
+        self
+    }
+
+    /// Mirror the tile vertically.
+    pub fn flip_y(mut self) -> Self {
+        self.flip_y = true;
+        self
+    }
+
+    /// Queue a shadow of the tile offset by `offset` pixels and drawn with
+    /// `color` underneath it.
+    pub fn shadow(mut self, offset: Vector2<f32>, color: Color) -> Self {
+        self.shadow = Some((offset, color));
+        self
+    }
+
+    /// The scale to draw the tile with, before the `TileSet`'s integer scale.
+    fn tile_scale(&self) -> graphics::Point2 {
+        match (self.int_scale.filter(|&factor| factor > 0), self.scale) {
+            (Some(factor), _) => compat::point(factor as f32, factor as f32),
+            (None, Some(scale)) => scale,
+            (None, None) => compat::point(1.0, 1.0),
+        }
     }
 }
 
-/// Additional parameters for drawing tiles.
-pub struct TileParams {
-    /// The optional color to draw the tile with.
-    pub color: Option<Color>,
-    /// Scale factor for drawing. Default is `1.0` (no scaling).
-    pub scale: Option<graphics::Point2>,
+impl<'a> From<&'a TileParams> for TileParams {
+    fn from(params: &'a TileParams) -> TileParams {
+        *params
+    }
 }
 
 impl From<(Option<Color>, Option<graphics::Point2>)> for TileParams {
     fn from((color, scale): (Option<Color>, Option<graphics::Point2>)) -> TileParams {
-        TileParams { color, scale }
+        TileParams {
+            color,
+            scale,
+            int_scale: None,
+            rotation: None,
+            emissive: false,
+            flip_x: false,
+            flip_y: false,
+            shadow: None,
+        }
     }
 }
 
@@ -122,6 +2805,12 @@ impl From<(Option<Color>, graphics::Point2)> for TileParams {
         TileParams {
             color,
             scale: Some(scale),
+            int_scale: None,
+            rotation: None,
+            emissive: false,
+            flip_x: false,
+            flip_y: false,
+            shadow: None,
         }
     }
 }
@@ -131,6 +2820,12 @@ impl From<(Color, Option<graphics::Point2>)> for TileParams {
         TileParams {
             color: Some(color),
             scale,
+            int_scale: None,
+            rotation: None,
+            emissive: false,
+            flip_x: false,
+            flip_y: false,
+            shadow: None,
         }
     }
 }
@@ -140,6 +2835,12 @@ impl From<(Color, graphics::Point2)> for TileParams {
         TileParams {
             color: Some(color),
             scale: Some(scale),
+            int_scale: None,
+            rotation: None,
+            emissive: false,
+            flip_x: false,
+            flip_y: false,
+            shadow: None,
         }
     }
 }
@@ -151,6 +2852,19 @@ pub enum TileSetError {
     OutOfRange,
     /// Tile not found.
     TileNotFound,
+    /// Registering the tile would exceed the maximum number of tiles.
+    CapacityExceeded,
+    /// The number of keys didn't match the number of tiles to register.
+    CountMismatch,
+    /// Queueing the tile would exceed the batch capacity.
+    BatchFull,
+    /// The variant weights were empty, negative or all zero.
+    InvalidWeights,
+    /// A size or width was zero or negative.
+    InvalidSize,
+    /// The tilesheet size, tile size or margins differed from the other sets
+    /// in a `TileSetGroup`.
+    SheetMismatch,
 }
 
 impl std::fmt::Display for TileSetError {
@@ -161,9 +2875,613 @@ impl std::fmt::Display for TileSetError {
             match self {
                 TileSetError::OutOfRange => "Position out of range of tilesheet dimensions",
                 TileSetError::TileNotFound => "Tile not found during lookup",
+                TileSetError::CapacityExceeded => "Maximum number of registered tiles exceeded",
+                TileSetError::CountMismatch => "Number of keys doesn't match number of tiles",
+                TileSetError::BatchFull => "Batch capacity exceeded",
+                TileSetError::InvalidWeights => "Variant weights are negative or all zero",
+                TileSetError::InvalidSize => "Size must be greater than zero",
+                TileSetError::SheetMismatch => "Tilesheet layout differs from the group",
             }
         )
     }
 }
 
 impl std::error::Error for TileSetError {}
+
+impl From<TileSetError> for ggez::GameError {
+    fn from(err: TileSetError) -> ggez::GameError {
+        ggez::GameError::RenderError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn virtual_set() -> TileSet<u32> {
+        let mut set = TileSet::new_virtual([4, 4], [16, 16]);
+        set.register_tile(0, [0, 0]).unwrap();
+        set
+    }
+
+    /// Where the top-left, top-right, bottom-right and bottom-left corners of
+    /// the tile texture end up on screen when `param` is drawn, scaling the
+    /// unit quad by the size of the source rectangle in pixels like `ggez`.
+    fn screen_corners(set: &TileSet<u32>, param: DrawParam) -> Vec<(i32, i32)> {
+        let real_scale = compat::point(
+            param.src.w * param.scale.x * set.sheet_size.x as f32,
+            param.src.h * param.scale.y * set.sheet_size.y as f32,
+        );
+        let matrix = compat::with_src_scale(param, param.src, real_scale).into_matrix();
+
+        [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| {
+                let corner = matrix * nalgebra::Vector4::new(x, y, 0.0, 1.0);
+                (corner.x.round() as i32, corner.y.round() as i32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn all_eight_orientations() {
+        let set = virtual_set();
+        let cell = [(16, 16), (32, 16), (32, 32), (16, 32)];
+        let mut seen = HashSet::new();
+
+        for quarter_turns in 0..4 {
+            for &flip_x in &[false, true] {
+                let rotation = quarter_turns as f32 * FRAC_PI_2;
+                let mut options = TileParams::new().rotation(rotation);
+                options.flip_x = flip_x;
+                let param = set.tile_draw_param(&0, [1, 1], Some(options)).unwrap();
+
+                // Mirroring swaps the left and right corners, then each
+                // quarter turn moves every corner one step clockwise.
+                let unturned = if flip_x { [1, 0, 3, 2] } else { [0, 1, 2, 3] };
+                let expected: Vec<_> = unturned
+                    .iter()
+                    .map(|corner| cell[(corner + quarter_turns) % 4])
+                    .collect();
+
+                let corners = screen_corners(&set, param);
+                assert_eq!(corners, expected, "{} turns, flip_x {}", quarter_turns, flip_x);
+                assert!(seen.insert(corners));
+            }
+        }
+
+        assert_eq!(seen.len(), 8);
+    }
+
+    #[test]
+    fn flip_y_is_flip_x_turned_half_way() {
+        let set = virtual_set();
+        let flip_y = set
+            .tile_draw_param(&0, [1, 1], Some(TileParams::new().flip_y()))
+            .unwrap();
+        let mut turned_flip_x = TileParams::new().rotation(2.0 * FRAC_PI_2);
+        turned_flip_x.flip_x = true;
+        let turned_flip_x = set.tile_draw_param(&0, [1, 1], Some(turned_flip_x)).unwrap();
+
+        assert_eq!(
+            screen_corners(&set, flip_y),
+            [(16, 32), (32, 32), (32, 16), (16, 16)]
+        );
+        assert_eq!(screen_corners(&set, flip_y), screen_corners(&set, turned_flip_x));
+    }
+
+    #[test]
+    fn virtual_set_queues_tiles() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+        set.register_tile(1, [1, 2]).unwrap();
+        set.queue_tile(1, [2, 1], None::<TileParams>).unwrap();
+        set.queue_tile(1, [0, 3], None::<TileParams>).unwrap();
+
+        let queued: Vec<_> = set.queued_tiles().collect();
+        assert_eq!(queued.len(), 2);
+        assert_eq!(queued[0].0, Point2 { x: 32.0, y: 16.0 });
+        assert_eq!(queued[0].1, Rect::new(0.25, 0.5, 0.25, 0.25));
+        assert_eq!(queued[1].0, Point2 { x: 0.0, y: 48.0 });
+
+        set.clear_queue();
+        assert_eq!(set.queued_tiles().count(), 0);
+    }
+
+    #[test]
+    fn virtual_set_rejects_unknown_and_out_of_range_tiles() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+
+        match set.register_tile(0, [4, 0]) {
+            Err(TileSetError::OutOfRange) => {}
+            other => panic!("expected OutOfRange, got {:?}", other),
+        }
+        match set.queue_tile(0, [0, 0], None::<TileParams>) {
+            Err(TileSetError::TileNotFound) => {}
+            other => panic!("expected TileNotFound, got {:?}", other),
+        }
+        assert_eq!(set.queued_tiles().count(), 0);
+    }
+
+    /// Assert that `src` is within the UV bounds of the tilesheet and within
+    /// the cell of the tile at `index`.
+    fn assert_src_in_cell<Key: Hash + Eq>(set: &TileSet<Key>, index: Point2<i32>, src: Rect) {
+        let eps = 1e-5;
+        let sheet_w = set.sheet_size.x as f32;
+        let sheet_h = set.sheet_size.y as f32;
+        let cell = set.debug_tile_pixel_rect(index);
+
+        assert!(
+            src.x >= -eps && src.y >= -eps && src.x + src.w <= 1.0 + eps
+                && src.y + src.h <= 1.0 + eps,
+            "{:?} of {:?} is outside of the tilesheet",
+            src,
+            index
+        );
+        assert!(
+            src.x >= cell.x / sheet_w - eps && src.y >= cell.y / sheet_h - eps
+                && src.x + src.w <= (cell.x + cell.w) / sheet_w + eps
+                && src.y + src.h <= (cell.y + cell.h) / sheet_h + eps,
+            "{:?} is outside of the cell of {:?}",
+            src,
+            index
+        );
+    }
+
+    #[test]
+    fn src_rects_stay_inside_their_cells() {
+        let margins = [
+            Margins::default(),
+            Margins {
+                top: 1,
+                left: 2,
+                right: 0,
+                bottom: 3,
+            },
+        ];
+
+        for &tile_size in &[[1, 1], [8, 8], [16, 8], [5, 7]] {
+            for dims_x in 1..5 {
+                for dims_y in 1..4 {
+                    for &margins in &margins {
+                        for &inset in &[[0, 0], [1, 0], [1, 2]] {
+                            if 2 * inset[0] >= tile_size[0] || 2 * inset[1] >= tile_size[1] {
+                                continue;
+                            }
+
+                            let mut set: TileSet<Point2<i32>> =
+                                TileSet::new_virtual([dims_x, dims_y], tile_size)
+                                    .with_margins(margins);
+                            set.set_cell_inset(inset).unwrap();
+
+                            let indices: Vec<_> = set.sheet_cells().collect();
+                            for &index in &indices {
+                                set.register_tile(index, index).unwrap();
+                                set.queue_tile(index, [0, 0], None::<TileParams>).unwrap();
+                            }
+
+                            assert_eq!(set.queued_tiles().count(), indices.len());
+                            for ((_, src), &index) in set.queued_tiles().zip(&indices) {
+                                assert_src_in_cell(&set, index, src);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_inset_and_flips_keep_src_inside_the_cell() {
+        let rotations = [0.0, FRAC_PI_2, 2.0 * FRAC_PI_2, 3.0 * FRAC_PI_2, 0.3];
+        let flips = [(false, false), (true, false), (false, true), (true, true)];
+
+        for &tile_size in &[[8, 8], [6, 10]] {
+            for &inset in &[[0, 0], [1, 1], [2, 1]] {
+                let mut set: TileSet<Point2<i32>> = TileSet::new_virtual([3, 2], tile_size);
+                set.set_cell_inset(inset).unwrap();
+                let indices: Vec<_> = set.sheet_cells().collect();
+                for &index in &indices {
+                    set.register_tile(index, index).unwrap();
+                }
+
+                for &index in &indices {
+                    let plain = set.tile_draw_param(&index, [0, 0], None::<TileParams>).unwrap();
+
+                    for &rotation in &rotations {
+                        for &(flip_x, flip_y) in &flips {
+                            let options = TileParams {
+                                rotation: Some(rotation),
+                                flip_x,
+                                flip_y,
+                                ..TileParams::default()
+                            };
+                            let param = set.tile_draw_param(&index, [1, 1], Some(options)).unwrap();
+
+                            assert_eq!(param.src, plain.src);
+                            assert_src_in_cell(&set, index, param.src);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn queue_index_map_rejects_zero_width() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+
+        match set.queue_index_map(&[0, 1], 0, [0, 0], None::<TileParams>) {
+            Err(TileSetError::InvalidSize) => {}
+            other => panic!("expected InvalidSize, got {:?}", other),
+        }
+        set.queue_index_map(&[], 0, [0, 0], None::<TileParams>).unwrap();
+        set.queue_index_map(&[5, 6, 7], 2, [1, 1], None::<TileParams>).unwrap();
+
+        let dests: Vec<_> = set.queued_tiles().map(|(dest, _)| dest).collect();
+        assert_eq!(
+            dests,
+            vec![
+                Point2 { x: 16.0, y: 16.0 },
+                Point2 { x: 32.0, y: 16.0 },
+                Point2 { x: 16.0, y: 32.0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn variants_are_part_of_the_registrations() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+        set.register_variants(0, vec![([3, 3].into(), 1.0), ([0, 0].into(), 2.0)])
+            .unwrap();
+
+        let unused = set.unused_cells();
+        assert!(!unused.contains(&[3, 3].into()) && !unused.contains(&[0, 0].into()));
+        assert_eq!(unused.len(), 14);
+
+        match set.set_tile_size([32, 32]) {
+            Err(TileSetError::OutOfRange) => {}
+            other => panic!("expected OutOfRange, got {:?}", other),
+        }
+        set.queue_variant_stable(&0, [0, 0], None::<TileParams>).unwrap();
+
+        set.reset();
+        match set.queue_variant_stable(&0, [0, 0], None::<TileParams>) {
+            Err(TileSetError::TileNotFound) => {}
+            other => panic!("expected TileNotFound, got {:?}", other),
+        }
+        assert_eq!(set.unused_cells().len(), 16);
+    }
+
+    #[test]
+    fn edits_after_undo_discard_redo() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+        set.register_tile(1, [0, 0]).unwrap();
+        set.register_tile(2, [1, 0]).unwrap();
+        let mut map = TileMap::new(set);
+
+        map.begin_stroke();
+        map.set([0, 0], 1).unwrap();
+        map.end_stroke();
+
+        assert!(map.undo());
+        assert_eq!(map.get([0, 0]), None);
+
+        map.set([0, 0], 2).unwrap();
+        assert!(!map.redo());
+        assert_eq!(map.get([0, 0]), Some(&2));
+    }
+
+    #[test]
+    fn set_tile_size_rejects_empty_tiles() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+
+        for &size in &[[0, 8], [8, 0], [-8, 8]] {
+            match set.set_tile_size(size) {
+                Err(TileSetError::InvalidSize) => {}
+                other => panic!("expected InvalidSize for {:?}, got {:?}", size, other),
+            }
+        }
+
+        set.set_tile_size([8, 8]).unwrap();
+        set.register_tile(0, [7, 7]).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "integer scale must be at least 1")]
+    fn integer_scale_of_zero_panics() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+        set.set_integer_scale(0);
+    }
+
+    #[test]
+    fn shadows_are_queued_first_and_never_emissive() {
+        let mut set = virtual_set();
+        let options = TileParams::new()
+            .emissive()
+            .shadow(Vector2 { x: 2.0, y: 2.0 }, Color::new(0.0, 0.0, 0.0, 0.5));
+
+        set.queue_tile(0, [1, 1], Some(options)).unwrap();
+        set.queue_tile_rect(0, Rect::new(0.0, 0.0, 32.0, 32.0), Some(options))
+            .unwrap();
+        set.queue_tile_scrolled(0, [2, 2], Vector2 { x: 0.0, y: 0.0 }, Some(options))
+            .unwrap();
+
+        let emissive: Vec<bool> = set.queued.iter().map(|placement| placement.emissive).collect();
+        assert_eq!(emissive, [false, true, false, true, false, true]);
+
+        let dests: Vec<(f32, f32)> = set.queued_tiles().map(|(dest, _)| (dest.x, dest.y)).collect();
+        assert_eq!(dests[0], (18.0, 18.0));
+        assert_eq!(dests[1], (16.0, 16.0));
+        assert_eq!(dests[4], (34.0, 34.0));
+        assert_eq!(dests[5], (32.0, 32.0));
+    }
+
+    #[test]
+    fn rle_rows_and_sparse_maps_leave_out_the_skip_index() {
+        let mut set = virtual_set();
+        set.register_tile(1, [1, 0]).unwrap();
+        set.set_skip_index(Some([0, 0].into()));
+
+        set.queue_rle_row(&[(0, 2), (1, 1)], 0, [0, 0], None::<TileParams>)
+            .unwrap();
+        let mut sparse = HashMap::new();
+        sparse.insert(Point2 { x: 0, y: 1 }, 0);
+        sparse.insert(Point2 { x: 1, y: 1 }, 1);
+        set.queue_sparse(&sparse, [0, 0], None::<TileParams>).unwrap();
+
+        let dests: Vec<(f32, f32)> = set.queued_tiles().map(|(dest, _)| (dest.x, dest.y)).collect();
+        assert_eq!(dests, [(32.0, 0.0), (16.0, 16.0)]);
+    }
+
+    #[test]
+    fn key_at_pixel_looks_through_the_camera() {
+        let mut set = virtual_set();
+        set.register_tile(1, [1, 0]).unwrap();
+        set.set_camera(Camera {
+            position: Point2 { x: 32.0, y: 0.0 },
+            zoom: 2.0,
+        });
+        let mut map = TileMap::new(set);
+        map.set([2, 0], 1).unwrap();
+
+        assert_eq!(map.key_at_pixel(Point2 { x: 10.0, y: 10.0 }), Some(&1));
+        assert_eq!(map.key_at_pixel(Point2 { x: 40.0, y: 10.0 }), None);
+    }
+
+    #[test]
+    fn tile_uv_size_matches_the_queued_src() {
+        let mut set = virtual_set();
+        set.set_cell_inset([1, 2]).unwrap();
+        set.queue_tile(0, [0, 0], None::<TileParams>).unwrap();
+
+        let uv = set.tile_uv_size();
+        let (_, src) = set.queued_tiles().next().unwrap();
+        assert_eq!((uv.x, uv.y), (src.w, src.h));
+        assert_eq!((uv.x, uv.y), (14.0 / 64.0, 12.0 / 64.0));
+    }
+
+    #[test]
+    fn tracked_sets_leave_their_own_batch_alone() {
+        let mut tracked = TrackedTileSet::new(virtual_set());
+        assert!(!tracked.tile_set().fills_batch);
+
+        tracked.tile_set_mut().queue_tile(0, [0, 0], None::<TileParams>).unwrap();
+        let set = tracked.into_inner();
+        assert!(set.fills_batch);
+        assert_eq!(set.queued_tiles().count(), 1);
+    }
+
+    #[test]
+    fn groups_reject_sets_with_a_different_layout() {
+        let mut group = TileSetGroup::new();
+        assert_eq!(group.push(virtual_set()).unwrap(), 0);
+        assert_eq!(group.push(virtual_set()).unwrap(), 1);
+
+        let margined = virtual_set().with_margins(Margins {
+            top: 1,
+            ..Margins::default()
+        });
+        let layouts = vec![TileSet::new_virtual([4, 4], [8, 8]), margined];
+
+        for set in layouts {
+            match group.push(set) {
+                Err(TileSetError::SheetMismatch) => {}
+                other => panic!("expected SheetMismatch, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn queue_map_tinted_rejects_zero_width() {
+        let mut set = virtual_set();
+        let white = |_| Color::new(1.0, 1.0, 1.0, 1.0);
+
+        match set.queue_map_tinted(&[0], 0, [0, 0], white, None::<TileParams>) {
+            Err(TileSetError::InvalidSize) => {}
+            other => panic!("expected InvalidSize, got {:?}", other),
+        }
+        set.queue_map_tinted(&[], 0, [0, 0], white, None::<TileParams>)
+            .unwrap();
+    }
+
+    #[test]
+    fn entries_modify_and_insert_within_the_tilesheet() {
+        let mut set = virtual_set();
+
+        let index = set.entry(0).and_modify(|index| index.x += 1).unwrap().or_register([3, 3]);
+        assert_eq!(index.unwrap(), Point2 { x: 1, y: 0 });
+        match set.entry(0).and_modify(|index| index.x += 3) {
+            Err(TileSetError::OutOfRange) => {}
+            _ => panic!("expected OutOfRange"),
+        }
+        assert_eq!(set.entry(0).or_register([3, 3]).unwrap(), Point2 { x: 1, y: 0 });
+
+        assert_eq!(set.entry(1).insert([2, 2]).unwrap(), None);
+        assert_eq!(set.entry(1).insert([3, 2]).unwrap(), Some(Point2 { x: 2, y: 2 }));
+        match set.entry(1).insert([4, 0]) {
+            Err(TileSetError::OutOfRange) => {}
+            other => panic!("expected OutOfRange, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn validate_reports_the_out_of_range_registrations() {
+        let mut set = virtual_set();
+        set.register_tile(1, [3, 0]).unwrap();
+        set.register_variants(2, vec![([1, 1].into(), 1.0), ([3, 3].into(), 1.0)])
+            .unwrap();
+        assert!(set.validate().is_ok());
+
+        let set = set.with_margins(Margins {
+            right: 16,
+            ..Margins::default()
+        });
+        let mut invalid = set.validate().unwrap_err();
+        invalid.sort_by_key(|&(&key, _)| key);
+        assert_eq!(invalid, [(&1, Point2 { x: 3, y: 0 }), (&2, Point2 { x: 3, y: 3 })]);
+    }
+
+    #[test]
+    fn cell_insets_must_leave_part_of_the_tile() {
+        let mut set = virtual_set();
+
+        for &inset in &[[8, 0], [0, 8], [-1, 0], [10, 10]] {
+            match set.set_cell_inset(inset) {
+                Err(TileSetError::InvalidSize) => {}
+                other => panic!("expected InvalidSize for {:?}, got {:?}", inset, other),
+            }
+        }
+        set.set_cell_inset([7, 7]).unwrap();
+
+        match set.set_tile_size([14, 16]) {
+            Err(TileSetError::InvalidSize) => {}
+            other => panic!("expected InvalidSize, got {:?}", other),
+        }
+        set.set_tile_size([15, 15]).unwrap();
+    }
+
+    #[test]
+    fn text_size_matches_the_queued_text() {
+        let mut set: TileSet<char> = TileSet::new_virtual([4, 4], [16, 16]);
+        set.register_tile('a', [0, 0]).unwrap();
+        set.register_tile('b', [1, 0]).unwrap();
+
+        for &text in &["ab", "a   b", "  ab", "ab  ", "a\n b  a \n  bb"] {
+            set.clear_queue();
+            set.queue_text_wrapped(text, [0, 0], 1000, None::<TileParams>)
+                .unwrap();
+
+            let extent = set.queued_tiles().fold((0, 0), |(w, h), (dest, _)| {
+                (w.max(dest.x as i32 + 16), h.max(dest.y as i32 + 16))
+            });
+            let size = set.text_size(text);
+            assert_eq!((size.x, size.y), extent, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn quarter_turns_of_tiles_that_arent_square_register() {
+        let mut set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 8]);
+        assert!(!set.is_square_tile());
+
+        set.register_tile_rotated(0, [0, 0], FRAC_PI_2).unwrap();
+        set.queue_tile(0, [0, 0], Some(TileParams::new().rotation(FRAC_PI_2)))
+            .unwrap();
+    }
+
+    #[test]
+    fn array_registration_keeps_the_old_tile_when_the_sheet_is_full() {
+        let full = TileSet::new_virtual([4, 4], [16, 16]).with_max_tiles(0);
+        let mut array = TileSetArray {
+            sets: vec![virtual_set(), full],
+        };
+
+        match array.register_tile(0, 1, [1, 1]) {
+            Err(TileSetError::CapacityExceeded) => {}
+            other => panic!("expected CapacityExceeded, got {:?}", other),
+        }
+        array.queue_tile(0, [0, 0], None::<TileParams>).unwrap();
+
+        array.register_tile(0, 0, [2, 2]).unwrap();
+        array.queue_tile(0, [0, 0], None::<TileParams>).unwrap();
+    }
+
+    #[test]
+    fn variant_keys_count_toward_the_maximum() {
+        let mut set = virtual_set().with_max_tiles(2);
+        let variants = || vec![([1, 1].into(), 1.0)];
+
+        set.register_variants(0, variants()).unwrap();
+        set.register_variants(1, variants()).unwrap();
+        set.register_variants(1, variants()).unwrap();
+        match set.register_variants(2, variants()) {
+            Err(TileSetError::CapacityExceeded) => {}
+            other => panic!("expected CapacityExceeded, got {:?}", other),
+        }
+        match set.register_tile(3, [0, 0]) {
+            Err(TileSetError::CapacityExceeded) => {}
+            other => panic!("expected CapacityExceeded, got {:?}", other),
+        }
+        set.register_tile(1, [2, 2]).unwrap();
+    }
+
+    #[test]
+    fn bounded_rects_match_the_inset_sprite() {
+        let mut set = virtual_set();
+        set.set_cell_inset([2, 2]).unwrap();
+
+        let bounds = set.queue_tile_bounded(0, [1, 1], None::<TileParams>).unwrap();
+        assert_eq!(bounds, Rect::new(18.0, 18.0, 12.0, 12.0));
+
+        let (dest, src) = set.queued_tiles().next().unwrap();
+        assert_eq!((dest.x, dest.y), (bounds.x, bounds.y));
+        assert_eq!((src.w * 64.0, src.h * 64.0), (bounds.w, bounds.h));
+    }
+
+    #[test]
+    fn exact_ranges_register_nothing_on_failure() {
+        let mut set = virtual_set().with_max_tiles(3);
+
+        match set.register_range_exact([0, 0], [i32::MAX, 2], vec![1, 2]) {
+            Err(TileSetError::CountMismatch) => {}
+            other => panic!("expected CountMismatch, got {:?}", other),
+        }
+        match set.register_range_exact([3, 3], [i32::MAX, 1], Vec::new()) {
+            Err(TileSetError::CountMismatch) => {}
+            other => panic!("expected CountMismatch, got {:?}", other),
+        }
+        match set.register_range_exact([i32::MAX, 0], [2, 1], vec![1, 2]) {
+            Err(TileSetError::OutOfRange) => {}
+            other => panic!("expected OutOfRange, got {:?}", other),
+        }
+        match set.register_range_exact([0, 1], [3, 1], vec![1, 2, 3]) {
+            Err(TileSetError::CapacityExceeded) => {}
+            other => panic!("expected CapacityExceeded, got {:?}", other),
+        }
+        assert_eq!(set.unused_cells().len(), 15);
+
+        set.register_range_exact([0, 1], [3, 1], vec![0, 1, 2]).unwrap();
+        assert_eq!(set.unused_cells().len(), 13);
+    }
+
+    #[test]
+    fn int_scale_of_zero_falls_back_to_scale() {
+        let set = virtual_set();
+        let scaled = |options: TileParams| {
+            let param = set.tile_draw_param(&0, [0, 0], Some(options)).unwrap();
+            (param.scale.x, param.scale.y)
+        };
+
+        assert_eq!(scaled(TileParams::new().int_scale(0)), (1.0, 1.0));
+        assert_eq!(scaled(TileParams::new().scale_xy(2.0, 3.0).int_scale(0)), (2.0, 3.0));
+        assert_eq!(scaled(TileParams::new().scale_xy(2.0, 3.0).int_scale(4)), (4.0, 4.0));
+    }
+
+    #[test]
+    #[should_panic(expected = "has no SpriteBatch")]
+    fn virtual_set_has_no_sprite_batch() {
+        let set: TileSet<u32> = TileSet::new_virtual([4, 4], [16, 16]);
+        set.sprite_batch();
+    }
+}
@@ -0,0 +1,55 @@
+//! Saving and loading the registered tiles of a `TileSet` as plain data, for
+//! writing to files with `serde`.
+
+use std::hash::Hash;
+use {TileSet, TileSetError};
+
+/// A snapshot of the tiles registered to a `TileSet`, without their metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileMapping<Key> {
+    /// The registered tiles, in no particular order.
+    pub tiles: Vec<MappedTile<Key>>,
+}
+
+/// A single registered tile in a `TileMapping`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MappedTile<Key> {
+    /// The lookup value of the tile.
+    pub key: Key,
+    /// The index of the tile in the tilesheet.
+    pub index: [i32; 2],
+    /// The rotation the tile was registered with, in radians.
+    #[serde(default)]
+    pub rotation: f32,
+}
+
+impl<Key: Hash + Eq + Clone, M> TileSet<Key, M> {
+    /// Snapshot the registered tiles and their rotations into a
+    /// `TileMapping`, which can be written to a file and registered again
+    /// with `load_mapping`.
+    pub fn save_mapping(&self) -> TileMapping<Key> {
+        let tiles = self.tile_cache
+            .iter()
+            .map(|(key, tile)| MappedTile {
+                key: key.clone(),
+                index: [tile.index.x, tile.index.y],
+                rotation: tile.rotation,
+            })
+            .collect();
+
+        TileMapping { tiles }
+    }
+}
+
+impl<Key: Hash + Eq, M> TileSet<Key, M> {
+    /// Register every tile in `mapping`, such as one saved with
+    /// `save_mapping`. Fails with the first error from registering a tile,
+    /// keeping the tiles registered before it.
+    pub fn load_mapping(&mut self, mapping: TileMapping<Key>) -> Result<(), TileSetError> {
+        for tile in mapping.tiles {
+            self.register_tile_rotated(tile.key, tile.index, tile.rotation)?;
+        }
+
+        Ok(())
+    }
+}